@@ -0,0 +1,357 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # ARM Timer
+//!
+//! Fixed rate periodic tick based on the ARM timer peripheral. In contrast to the one-shot compare channels of the
+//! system timer the ARM timer reloads its counter automatically, so the tick rate does not depend on how fast the
+//! interrupt is handled.
+//!
+//! The periodic tick is raised with the ARM timer interrupt, so it is only available with the optional
+//! ``ruspiro-interrupt`` dependency. The free running counter of the ARM timer can be used without it.
+//!
+//! ## Resolution
+//! The timer is clocked from the APB clock which is pre-divided to 1MHz, assuming the default core clock of 250MHz.
+//! The 32Bit counter allows periods up to ~71 minutes with a resolution of 1µs. Longer periods use the 16 or 256
+//! pre-scaler which reduces the resolution to 16µs or 256µs respectively. Periods longer than ~305 hours are
+//! clamped to this maximum. If the core clock is changed (e.g. with ``core_freq`` in ``config.txt``) the period
+//! scales accordingly.
+//!
+//! ## Ticker
+//! A [Ticker] counts the ticks of the periodic timer without a tick handler. Loops that need a steady rate, like
+//! animations, wait for the next tick with [Ticker::wait_next] and derive slower events by counting ticks instead of
+//! scheduling many one-shot functions.
+//!
+//! # Example
+//! With the ``ruspiro-interrupt`` dependency a tick handler is called at a fixed rate
+//! ```no_run
+//! # use ruspiro_timer::*;
+//! # #[cfg(feature = "ruspiro-interrupt")]
+//! # fn doc() {
+//! arm_timer::set_tick_handler(|| println!("tick"));
+//! arm_timer::start_periodic(Duration::from_millis(10));
+//! // ... later on
+//! arm_timer::stop();
+//! # }
+//! ```
+
+use crate::clock;
+use crate::interface::*;
+#[cfg(feature = "ruspiro-interrupt")]
+use crate::Duration;
+#[cfg(feature = "ruspiro-interrupt")]
+use alloc::boxed::Box;
+#[cfg(feature = "ruspiro-interrupt")]
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+#[cfg(feature = "ruspiro-interrupt")]
+use ruspiro_interrupt::{self as irq, Interrupt, IrqHandler, IsrSender};
+#[cfg(feature = "ruspiro-interrupt")]
+use ruspiro_singleton::Singleton;
+
+/// The clock the ARM timer is driven with. This is derived from the core clock running at 250MHz by default
+const ARM_TIMER_CLOCK_HZ: u32 = 250_000_000;
+
+/// The frequency the ARM timer is counting with after the pre-divider has been applied
+#[cfg(feature = "ruspiro-interrupt")]
+const ARM_TIMER_TICK_HZ: u32 = 1_000_000;
+
+#[cfg(feature = "ruspiro-interrupt")]
+type TickFunction = Box<dyn FnMut() + 'static + Send>;
+
+/// The function to be called each time the ARM timer has counted down to 0
+#[cfg(feature = "ruspiro-interrupt")]
+struct TickHandler(Option<TickFunction>);
+
+// the tick handler is only ever accessed with exclusive access through the singleton
+#[cfg(feature = "ruspiro-interrupt")]
+unsafe impl Sync for TickHandler {}
+
+#[cfg(feature = "ruspiro-interrupt")]
+static TICK_HANDLER: Singleton<TickHandler> = Singleton::new(TickHandler(None));
+
+/// Flag indicating that the periodic tick is currently running
+#[cfg(feature = "ruspiro-interrupt")]
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// The number of ticks raised by the periodic ARM timer so far
+#[cfg(feature = "ruspiro-interrupt")]
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// The frequency of the free running counter in Hz, 0 as long as it has not been configured
+static FREE_RUNNING_HZ: AtomicU32 = AtomicU32::new(0);
+
+/// The system timer counter value at the beginning of a micro second used as reference for the free running counter
+static REFERENCE_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// The free running counter value at the time of [REFERENCE_TICKS]
+static REFERENCE_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// Register the function that shall be called from the ARM timer interrupt at each tick. A previously registered
+/// function is replaced. The interrupt is briefly deactivated while the function is replaced so this can safely be
+/// called while the periodic tick is running.
+#[cfg(feature = "ruspiro-interrupt")]
+pub fn set_tick_handler<F: FnMut() + 'static + Send>(function: F) {
+  let running = RUNNING.load(Ordering::Acquire);
+  if running {
+    irq::deactivate(Interrupt::ArmTimer);
+  }
+  TICK_HANDLER.with_mut(|handler| handler.0.replace(Box::new(function)));
+  if running {
+    irq::activate(Interrupt::ArmTimer, None);
+  }
+}
+
+/// Start the ARM timer to raise an interrupt each time the given period has passed. The registered tick handler is
+/// called from this interrupt. Starting an already running timer re-programs it with the new period.
+///
+/// ## Hint:
+/// The interrupts need to be initialized and globally enabled using the
+/// [``ruspiro_interrupt`` crate](https://crates.io/crates/ruspiro_interrupt) for the tick handler to be called.
+#[cfg(feature = "ruspiro-interrupt")]
+pub fn start_periodic(period: Duration) {
+  program_periodic(period);
+}
+
+/// Program and start the periodic tick and return the period actually achieved with the pre-scaler chosen
+#[cfg(feature = "ruspiro-interrupt")]
+fn program_periodic(period: Duration) -> Duration {
+  let micros = period.as_micros().max(1);
+  let (prescaler, divider, ticks) = if micros <= u32::MAX as u128 {
+    (ARM_TIMERCTRL::PRESCALER::CLOCK_DIV_1, 1, micros)
+  } else if micros / 16 <= u32::MAX as u128 {
+    (ARM_TIMERCTRL::PRESCALER::CLOCK_DIV_16, 16, micros / 16)
+  } else {
+    (
+      ARM_TIMERCTRL::PRESCALER::CLOCK_DIV_256,
+      256,
+      (micros / 256).min(u32::MAX as u128),
+    )
+  };
+
+  // the timer need to be disabled while it's configuration is changed
+  ARM_TIMERCTRL().modify_value(ARM_TIMERCTRL::TIMER::DISABLED);
+  ARM_TIMERPREDIV().write(
+    ARM_TIMERPREDIV::VALUE,
+    ARM_TIMER_CLOCK_HZ / ARM_TIMER_TICK_HZ - 1,
+  );
+  // the counter raises the interrupt when reaching 0, so the period is one tick longer than the load value
+  ARM_TIMERLOAD().set(ticks as u32 - 1);
+  ARM_TIMERRELOAD().set(ticks as u32 - 1);
+  ARM_TIMERACKN().set(1);
+  ARM_TIMERCTRL().modify_value(
+    ARM_TIMERCTRL::WIDTH::_32Bit
+      | prescaler
+      | ARM_TIMERCTRL::IRQ::ENABLED
+      | ARM_TIMERCTRL::TIMER::ENABLED,
+  );
+
+  RUNNING.store(true, Ordering::Release);
+  irq::activate(Interrupt::ArmTimer, None);
+
+  Duration::from_micros((ticks * divider) as u64)
+}
+
+/// Stop the periodic tick of the ARM timer. The registered tick handler is kept and will be called again once the
+/// timer is re-started with [start_periodic].
+#[cfg(feature = "ruspiro-interrupt")]
+pub fn stop() {
+  irq::deactivate(Interrupt::ArmTimer);
+  RUNNING.store(false, Ordering::Release);
+  ARM_TIMERCTRL().modify_value(ARM_TIMERCTRL::IRQ::DISABLED | ARM_TIMERCTRL::TIMER::DISABLED);
+  ARM_TIMERACKN().set(1);
+}
+
+/// Steady tick at a fixed rate driven by the periodic ARM timer. Creating a ticker starts the periodic timer, which
+/// is stopped again when the ticker is dropped. As there is only one ARM timer there should only be one ticker at a
+/// time and the timer must not be started with a different period while the ticker is in use. A registered tick
+/// handler is still called at each tick.
+///
+/// ## Rate:
+/// The ARM timer counts at 1MHz, so the period of a tick is a whole number of micro seconds. A requested rate is
+/// rounded to the nearest period, e.g. 60Hz ticks every 16_667µs which is ~59.999Hz. Rates above 1MHz are clamped
+/// to 1MHz, but rates of more than a few 10kHz spend a significant amount of time in the interrupt. Very low rates
+/// with periods longer than ~71 minutes are rounded further by the pre-scaler, see the module documentation.
+/// [Ticker::period] returns the period actually achieved.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// let mut ticker = arm_timer::Ticker::new(60);
+/// loop {
+///   ticker.wait_next();
+///   // draw the next frame ...
+///   if ticker.ticks() % 60 == 0 {
+///     // once a second update the status line ...
+///   }
+/// #   break;
+/// }
+/// # }
+/// ```
+#[cfg(feature = "ruspiro-interrupt")]
+#[derive(Debug)]
+pub struct Ticker {
+  /// The period actually achieved for the requested rate
+  period: Duration,
+  /// The tick count of the timer when the ticker has been created
+  start: u64,
+  /// The tick count seen with the last call to [Ticker::wait_next]
+  seen: u64,
+}
+
+#[cfg(feature = "ruspiro-interrupt")]
+impl Ticker {
+  /// Create a new ticker firing with the given rate in Hz and start the periodic ARM timer
+  pub fn new(rate: u32) -> Self {
+    let rate = rate.clamp(1, ARM_TIMER_TICK_HZ);
+    let micros = (ARM_TIMER_TICK_HZ + rate / 2) / rate;
+    let period = program_periodic(Duration::from_micros(micros as u64));
+    let start = TICKS.load(Ordering::Acquire);
+    Self {
+      period,
+      start,
+      seen: start,
+    }
+  }
+
+  /// The period between two ticks actually achieved for the requested rate
+  pub fn period(&self) -> Duration {
+    self.period
+  }
+
+  /// The number of ticks since the ticker has been created
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_timer::*;
+  /// # fn doc() {
+  /// let mut ticker = arm_timer::Ticker::new(1_000);
+  /// assert_eq!(ticker.ticks(), 0);
+  /// for _ in 0..10 {
+  ///   ticker.wait_next();
+  /// }
+  /// assert_eq!(ticker.ticks(), 10);
+  /// # }
+  /// ```
+  pub fn ticks(&self) -> u64 {
+    TICKS.load(Ordering::Acquire) - self.start
+  }
+
+  /// Wait for the next tick and return the number of ticks passed since the previous call. A value larger than 1
+  /// indicates that the caller missed ticks, e.g. because the work in between took longer than the period. In this
+  /// case the call returns immediately so the caller can catch up.
+  pub fn wait_next(&mut self) -> u64 {
+    let mut ticks = TICKS.load(Ordering::Acquire);
+    while ticks == self.seen {
+      core::hint::spin_loop();
+      ticks = TICKS.load(Ordering::Acquire);
+    }
+    let passed = ticks - self.seen;
+    self.seen = ticks;
+    passed
+  }
+}
+
+#[cfg(feature = "ruspiro-interrupt")]
+impl Drop for Ticker {
+  fn drop(&mut self) {
+    stop();
+  }
+}
+
+/// Enable the free running counter of the ARM timer with the given pre-scaler. The counter runs at
+/// ``core clock / (prescaler + 1)``, independent of the periodic tick, and the resulting frequency in Hz is
+/// returned. With a pre-scaler of 0 the counter runs at the full core clock and provides a much finer resolution
+/// than the 1MHz system timer which is useful for micro benchmarks.
+///
+/// ## Hint:
+/// The free running counter is only 32Bit wide and wraps around, at full core clock within ~17 seconds. Use
+/// ``wrapping_sub`` to calculate the difference between two counter values and prefer [crate::now] for longer
+/// intervals.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// let freq = arm_timer::configure_free_running(0);
+/// let start = arm_timer::free_running_counter();
+/// sleepcycles(1_000);
+/// let ticks = arm_timer::free_running_counter().wrapping_sub(start);
+/// assert!(ticks > 0);
+/// println!("took {} ns", ticks as u64 * 1_000_000_000 / freq as u64);
+/// # }
+/// ```
+pub fn configure_free_running(prescaler: u8) -> u32 {
+  ARM_TIMERCTRL().modify_value(
+    ARM_TIMERCTRL::FR_PRESCALER::with_value(prescaler as u32) | ARM_TIMERCTRL::FREERUN::ENABLED,
+  );
+
+  // wait for the beginning of a new micro second of the system timer to take the reference value of the free
+  // running counter used by [now_ns](crate::now_ns)
+  let ticks = clock::ticks();
+  while clock::ticks() == ticks {}
+  REFERENCE_COUNTER.store(free_running_counter(), Ordering::Release);
+  REFERENCE_TICKS.store(ticks + 1, Ordering::Release);
+
+  let freq = ARM_TIMER_CLOCK_HZ / (prescaler as u32 + 1);
+  FREE_RUNNING_HZ.store(freq, Ordering::Release);
+  freq
+}
+
+/// The frequency the free running counter is counting with, ``None`` as long as it has not been configured with
+/// [configure_free_running]
+pub(crate) fn free_running_frequency() -> Option<u32> {
+  match FREE_RUNNING_HZ.load(Ordering::Acquire) {
+    0 => None,
+    freq => Some(freq),
+  }
+}
+
+/// Get the nano seconds that have passed within the micro second of the given system timer counter value, based on
+/// the free running counter value read at the same time. Returns ``None`` if the free running counter has not been
+/// configured.
+pub(crate) fn nanos_within_micro(ticks: u64, counter: u32) -> Option<u64> {
+  let freq = FREE_RUNNING_HZ.load(Ordering::Acquire) as u128;
+  if freq == 0 {
+    return None;
+  }
+  let ref_ticks = REFERENCE_TICKS.load(Ordering::Acquire);
+  let ref_counter = REFERENCE_COUNTER.load(Ordering::Acquire);
+  let elapsed_micros = ticks.saturating_sub(ref_ticks) as u128;
+  // the free running counter wraps around, use the system timer to find out how often this has happened since the
+  // reference has been taken
+  let expected = elapsed_micros * freq / 1_000_000;
+  let counted = counter.wrapping_sub(ref_counter) as u128;
+  let wraps = (expected.saturating_sub(counted) + (1 << 31)) >> 32;
+  let elapsed_nanos = (counted + (wraps << 32)) * 1_000_000_000 / freq;
+  // the remainder within the current micro second, the clamping compensates drifts between both clocks
+  let within = elapsed_nanos
+    .saturating_sub(elapsed_micros * 1_000)
+    .min(999);
+
+  Some(within as u64)
+}
+
+/// Get the current value of the free running counter of the ARM timer. The counter need to be enabled with
+/// [configure_free_running] first.
+pub fn free_running_counter() -> u32 {
+  ARM_TIMERFRCOUNTER().get()
+}
+
+/// Implement the ARM timer interrupt handler calling the registered tick handler
+#[cfg(feature = "ruspiro-interrupt")]
+#[IrqHandler(ArmTimer)]
+unsafe fn arm_timer_handler(channel: Option<IsrSender<Box<dyn Any>>>) {
+  // acknowledge the interrupt first, writing any value to the acknowledge register clears the pending flag
+  ARM_TIMERACKN().set(1);
+  TICKS.fetch_add(1, Ordering::AcqRel);
+  TICK_HANDLER.with_mut(|handler| {
+    if let Some(function) = handler.0.as_mut() {
+      function();
+    }
+  });
+}
@@ -0,0 +1,443 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Async Timer Functions
+//!
+//! Timer functions to be used within cooperative async runtimes. Instead of blocking the current
+//! core the futures provided here register the waker of the task to be woken once the time is due.
+//! The wake up is driven by the scheduler of this crate, so either the system timer interrupt or
+//! [poll_schedules](crate::poll_schedules) need to be active.
+//!
+
+use crate::*;
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+  cell::UnsafeCell,
+  future::Future,
+  pin::Pin,
+  sync::atomic::{AtomicU8, Ordering},
+  task::{Context, Poll, Waker},
+};
+use futures_core::Stream;
+
+/// The [WakerCell] is neither registering nor waking a waker
+const WAITING: u8 = 0;
+/// The [WakerCell] is storing a new waker
+const REGISTERING: u8 = 1;
+/// The [WakerCell] is taking the waker to wake it
+const WAKING: u8 = 2;
+
+/// Lock free cell holding the waker of a task. The waker is woken from within the timer interrupt, which must never
+/// wait for a lock that the interrupted code on the same core might hold. So instead of a lock the cell tracks
+/// whether a waker is being registered or woken. A wake up that happens while a new waker is registered is passed
+/// on to the registering side, which wakes the new waker right away.
+struct WakerCell {
+  state: AtomicU8,
+  waker: UnsafeCell<Option<Waker>>,
+}
+
+// The waker is only accessed by the side that has moved the state away from WAITING, so it's never accessed by two
+// cores or the interrupt handler and the interrupted code at the same time
+unsafe impl Send for WakerCell {}
+unsafe impl Sync for WakerCell {}
+
+impl WakerCell {
+  fn new() -> Self {
+    Self {
+      state: AtomicU8::new(WAITING),
+      waker: UnsafeCell::new(None),
+    }
+  }
+
+  /// Store the given waker to be woken by the next call to [WakerCell::wake]. This is only ever called by the single
+  /// task owning the [Delay].
+  fn register(&self, waker: &Waker) {
+    match self
+      .state
+      .compare_exchange(WAITING, REGISTERING, Ordering::Acquire, Ordering::Acquire)
+    {
+      Ok(_) => {
+        // this is safe as the state REGISTERING grants exclusive access to the waker
+        unsafe { *self.waker.get() = Some(waker.clone()) };
+        if self
+          .state
+          .compare_exchange(REGISTERING, WAITING, Ordering::AcqRel, Ordering::Acquire)
+          .is_err()
+        {
+          // a wake up has happened while registering, it is passed on to this side. The state still grants
+          // exclusive access to the waker
+          let waker = unsafe { (*self.waker.get()).take() };
+          self.state.store(WAITING, Ordering::Release);
+          if let Some(waker) = waker {
+            waker.wake();
+          }
+        }
+      }
+      // the waker is woken right now, wake the new one as well
+      Err(_) => waker.wake_by_ref(),
+    }
+  }
+
+  /// Wake the registered waker, if any. This never waits and is safe to be called from within the timer interrupt.
+  fn wake(&self) {
+    if self.state.fetch_or(WAKING, Ordering::AcqRel) == WAITING {
+      // this is safe as the state WAKING grants exclusive access to the waker
+      let waker = unsafe { (*self.waker.get()).take() };
+      self.state.fetch_and(!WAKING, Ordering::Release);
+      if let Some(waker) = waker {
+        waker.wake();
+      }
+    }
+  }
+}
+
+/// A future that completes once the given deadline has been reached. Dropping it before cancels the pending wake up,
+/// so it does not occupy the schedules until the deadline.
+pub struct Delay {
+  /// The time this future is due
+  deadline: Duration,
+  /// The waker of the task polling this future, to be woken once the deadline is reached
+  waker: Option<Arc<WakerCell>>,
+  /// The handle of the scheduled wake up
+  wakeup: Option<ScheduleHandle>,
+}
+
+impl Delay {
+  /// Create a new [Delay] that completes at the given deadline
+  pub(crate) fn until(deadline: Duration) -> Self {
+    Self {
+      deadline,
+      waker: None,
+      wakeup: None,
+    }
+  }
+}
+
+impl Drop for Delay {
+  fn drop(&mut self) {
+    if let Some(wakeup) = self.wakeup.take() {
+      cancel(&wakeup);
+    }
+  }
+}
+
+impl Future for Delay {
+  type Output = ();
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+    if is_due(this.deadline) {
+      return Poll::Ready(());
+    }
+
+    match this.waker {
+      // the wake up is already scheduled, only update the waker as the task might have been moved
+      Some(ref waker) => waker.register(cx.waker()),
+      None => {
+        let waker = Arc::new(WakerCell::new());
+        waker.register(cx.waker());
+        let wakeup = Arc::clone(&waker);
+        this.wakeup = Some(schedule_at(this.deadline, move || wakeup.wake()));
+        this.waker.replace(waker);
+      }
+    }
+
+    Poll::Pending
+  }
+}
+
+/// Create a future that completes after the given duration
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::{*, async_timer::*};
+/// # async fn doc() {
+///     // pause the current task for 100 milliseconds
+///     delay(Duration::from_millis(100)).await;
+/// # }
+/// ```
+pub fn delay(duration: Duration) -> Delay {
+  Delay::until(now_plus(duration))
+}
+
+/// A future that is pending on the first poll and completes on the next one, see [yield_now]
+pub struct Yield {
+  yielded: bool,
+}
+
+impl Future for Yield {
+  type Output = ();
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+    if this.yielded {
+      return Poll::Ready(());
+    }
+
+    this.yielded = true;
+    // request to be polled again right away, the executor can run the other tasks first
+    cx.waker().wake_by_ref();
+    Poll::Pending
+  }
+}
+
+/// Give control back to the executor once, so other tasks of a single threaded executor get the chance to run. This
+/// is the cooperative counterpart of a zero-duration sleep and completes without any measurable delay. The blocking
+/// version is [yield_now](crate::yield_now).
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::async_timer::*;
+/// # use core::{future::Future, pin::Pin, ptr, task::*};
+/// # fn noop_raw_waker() -> RawWaker {
+/// #   fn clone(_: *const ()) -> RawWaker { noop_raw_waker() }
+/// #   fn noop(_: *const ()) {}
+/// #   static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+/// #   RawWaker::new(ptr::null(), &VTABLE)
+/// # }
+/// # fn doc() {
+/// let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+/// let mut cx = Context::from_waker(&waker);
+/// let mut yielded = yield_now();
+/// // the first poll gives control back to the executor, the next one completes
+/// assert_eq!(Pin::new(&mut yielded).poll(&mut cx), Poll::Pending);
+/// assert_eq!(Pin::new(&mut yielded).poll(&mut cx), Poll::Ready(()));
+/// # }
+/// ```
+pub fn yield_now() -> Yield {
+  Yield { yielded: false }
+}
+
+/// Run the given future but give up after the given duration. Returns the output of the future if it completes in
+/// time or [TimedOut] otherwise. The future is dropped when the time is up. If the future completes in time the
+/// pending wake up of the timeout is cancelled right away, so timeouts used in a loop never pile up in the schedules
+/// or count against the capacity of [try_schedule](crate::try_schedule).
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::{*, async_timer::*};
+/// # async fn doc() {
+///     match timeout(Duration::from_millis(10), delay(Duration::from_secs(1))).await {
+///       Ok(_) => println!("completed in time"),
+///       Err(TimedOut) => println!("took too long"),
+///     }
+/// # }
+/// ```
+pub async fn timeout<F: Future>(duration: Duration, fut: F) -> Result<F::Output, TimedOut> {
+  Race {
+    fut: Box::pin(fut),
+    delay: Some(delay(duration)),
+  }
+  .await
+}
+
+/// Future polling the inner future and the delay, completing with the one that is ready first
+struct Race<F: Future> {
+  fut: Pin<Box<F>>,
+  /// The delay of the timeout, dropped and thus cancelled once the future has completed
+  delay: Option<Delay>,
+}
+
+impl<F: Future> Future for Race<F> {
+  type Output = Result<F::Output, TimedOut>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.get_mut();
+    if let Poll::Ready(output) = this.fut.as_mut().poll(cx) {
+      this.delay = None;
+      return Poll::Ready(Ok(output));
+    }
+
+    match this.delay.as_mut().map(|delay| Pin::new(delay).poll(cx)) {
+      Some(Poll::Pending) => Poll::Pending,
+      _ => {
+        this.delay = None;
+        Poll::Ready(Err(TimedOut))
+      }
+    }
+  }
+}
+
+/// How an [Interval] catches up once it has not been polled in time and one or more period boundaries have been
+/// missed. The semantics are the same as the ones of the ``MissedTickBehavior`` known from tokio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissedTickBehavior {
+  /// Yield once for each missed boundary right away until the stream has caught up with the original schedule. This
+  /// is the default.
+  Burst,
+  /// Yield once for the missed boundaries and wait a whole period from then on, the period boundaries are shifted by
+  /// the time the stream has been late.
+  Delay,
+  /// Yield once for the missed boundaries and continue with the next boundary of the original schedule that is still
+  /// in the future, the missed ones are skipped silently.
+  Skip,
+}
+
+/// A stream yielding at a fixed period. The next period boundary is always calculated from the previous one and not
+/// from the time the stream has been polled, so the stream does not drift. How missed boundaries are handled is
+/// configured with [Interval::set_missed_tick_behavior].
+pub struct Interval {
+  /// The time between two yields of this stream
+  period: Duration,
+  /// How to continue once boundaries have been missed
+  missed_tick_behavior: MissedTickBehavior,
+  /// The period boundary the stream is waiting for
+  next: Duration,
+  /// The delay waiting for the next boundary
+  delay: Option<Delay>,
+}
+
+impl Interval {
+  /// The behavior of this stream once it has missed period boundaries
+  pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+    self.missed_tick_behavior
+  }
+
+  /// Configure how this stream catches up once it has missed period boundaries, the default is
+  /// [MissedTickBehavior::Burst].
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_timer::{*, async_timer::*};
+  /// # use core::{pin::Pin, ptr, task::*};
+  /// # use futures_core::Stream;
+  /// # fn noop_raw_waker() -> RawWaker {
+  /// #   fn clone(_: *const ()) -> RawWaker { noop_raw_waker() }
+  /// #   fn noop(_: *const ()) {}
+  /// #   static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+  /// #   RawWaker::new(ptr::null(), &VTABLE)
+  /// # }
+  /// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+  ///
+  /// # fn doc() {
+  /// set_time_source(&CLOCK);
+  /// let start = Instant::now();
+  /// let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+  /// let mut cx = Context::from_waker(&waker);
+  /// // the milliseconds since the start of the boundary yielded by the stream, if any
+  /// let mut tick = |ticks: &mut Interval| match Pin::new(ticks).poll_next(&mut cx) {
+  ///   Poll::Ready(Some(tick)) => Some(tick.duration_since(start).as_millis()),
+  ///   _ => None,
+  /// };
+  ///
+  /// let mut burst = interval(Duration::from_millis(10));
+  /// let mut delayed = interval(Duration::from_millis(10));
+  /// delayed.set_missed_tick_behavior(MissedTickBehavior::Delay);
+  /// let mut skipped = interval(Duration::from_millis(10));
+  /// skipped.set_missed_tick_behavior(MissedTickBehavior::Skip);
+  ///
+  /// // none of the streams is polled for 35 milliseconds
+  /// CLOCK.advance(35_000);
+  ///
+  /// // the missed boundaries are yielded right away
+  /// assert_eq!(tick(&mut burst), Some(10));
+  /// assert_eq!(tick(&mut burst), Some(20));
+  /// assert_eq!(tick(&mut burst), Some(30));
+  /// assert_eq!(tick(&mut burst), None);
+  ///
+  /// // the next boundary is one period after the late yield
+  /// assert_eq!(tick(&mut delayed), Some(10));
+  /// assert_eq!(tick(&mut delayed), None);
+  ///
+  /// // the next boundary is the next one of the original schedule
+  /// assert_eq!(tick(&mut skipped), Some(10));
+  /// assert_eq!(tick(&mut skipped), None);
+  ///
+  /// CLOCK.advance(10_000);
+  /// assert_eq!(tick(&mut delayed), Some(45));
+  /// assert_eq!(tick(&mut skipped), Some(40));
+  /// # }
+  /// ```
+  pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+    self.missed_tick_behavior = behavior;
+  }
+
+  /// The period boundary to wait for after the given one has been yielded. The boundaries saturate at
+  /// ``Duration::MAX``, which is never reached, so a huge period ends the ticks instead of panicking.
+  fn following(&self, yielded: Duration) -> Duration {
+    let current = now();
+    let following = yielded.checked_add(self.period).unwrap_or(Duration::MAX);
+    if following > current {
+      return following;
+    }
+
+    // the stream has been late for at least a whole period
+    match self.missed_tick_behavior {
+      MissedTickBehavior::Burst => following,
+      MissedTickBehavior::Delay => current.checked_add(self.period).unwrap_or(Duration::MAX),
+      MissedTickBehavior::Skip => {
+        let period = self.period.as_nanos();
+        if period == 0 {
+          return current;
+        }
+        let missed = (current - yielded).as_nanos() / period;
+        let ahead = (period * (missed + 1)).min(u64::MAX as u128) as u64;
+        yielded
+          .checked_add(Duration::from_nanos(ahead))
+          .unwrap_or(Duration::MAX)
+      }
+    }
+  }
+}
+
+impl Stream for Interval {
+  type Item = Instant;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+    let next = this.next;
+    let delay = this.delay.get_or_insert_with(|| Delay::until(next));
+
+    match Pin::new(delay).poll(cx) {
+      Poll::Ready(_) => {
+        this.delay = None;
+        this.next = this.following(next);
+        Poll::Ready(Some(Instant(next)))
+      }
+      Poll::Pending => Poll::Pending,
+    }
+  }
+}
+
+/// Create a stream that yields the [Instant] of each boundary of the given period. The first yield happens one
+/// period after the creation of the stream.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::{*, async_timer::*};
+/// # use futures_util::StreamExt;
+/// # async fn doc() {
+///     let mut ticks = interval(Duration::from_millis(100));
+///     while let Some(_) = ticks.next().await {
+///       println!("tick");
+///     }
+/// # }
+/// ```
+pub fn interval(period: Duration) -> Interval {
+  Interval {
+    period,
+    missed_tick_behavior: MissedTickBehavior::Burst,
+    next: now_plus(period),
+    delay: None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::setup;
+
+  #[test]
+  fn following_saturates_at_max() {
+    let _guard = setup();
+    for behavior in [
+      MissedTickBehavior::Burst,
+      MissedTickBehavior::Delay,
+      MissedTickBehavior::Skip,
+    ] {
+      let mut ticks = interval(Duration::MAX - Duration::from_secs(1));
+      ticks.set_missed_tick_behavior(behavior);
+
+      assert_eq!(ticks.following(Duration::from_secs(2)), Duration::MAX);
+    }
+  }
+}
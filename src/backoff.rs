@@ -0,0 +1,91 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Exponential Backoff
+//!
+//! Pause helper for retry loops like bus arbitration or polling a peripheral. Each wait doubles the pause up to a
+//! maximum, so the first retries happen quickly while a longer lasting condition does not keep the core busy with
+//! retrying.
+//!
+//! # Example
+//! ```no_run
+//! # use ruspiro_timer::*;
+//! # fn peripheral_ready() -> bool { true }
+//! # fn doc() {
+//! let mut backoff = backoff::Backoff::new(Duration::from_micros(10), Duration::from_millis(1));
+//! // waits 10µs, 20µs, 40µs ... up to 1ms between the checks
+//! while !peripheral_ready() {
+//!   backoff.wait();
+//! }
+//! # }
+//! ```
+
+use crate::{sleep, Duration};
+
+/// Exponentially growing pause between retries
+#[derive(Debug, Clone)]
+pub struct Backoff {
+  initial: Duration,
+  max: Duration,
+  current: Duration,
+}
+
+impl Backoff {
+  /// Create a new backoff starting with the ``initial`` pause that is doubled with each wait until it reaches
+  /// ``max``. If ``initial`` is larger than ``max`` each wait pauses for ``max``.
+  pub fn new(initial: Duration, max: Duration) -> Self {
+    let initial = initial.min(max);
+    Self {
+      initial,
+      max,
+      current: initial,
+    }
+  }
+
+  /// The pause the next call to [Backoff::wait] will take
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_timer::*;
+  /// # fn doc() {
+  /// let mut backoff = backoff::Backoff::new(Duration::from_micros(10), Duration::from_micros(50));
+  /// let mut sequence = [Duration::ZERO; 5];
+  /// for pause in sequence.iter_mut() {
+  ///   *pause = backoff.current();
+  ///   backoff.wait();
+  /// }
+  /// assert_eq!(sequence, [10, 20, 40, 50, 50].map(Duration::from_micros));
+  /// backoff.reset();
+  /// assert_eq!(backoff.current(), Duration::from_micros(10));
+  /// # }
+  /// ```
+  pub fn current(&self) -> Duration {
+    self.current
+  }
+
+  /// Pause for the current delay and double it for the next wait, capped at the maximum
+  pub fn wait(&mut self) {
+    sleep(self.current);
+    self.advance();
+  }
+
+  /// Start again with the initial pause, e.g. after the retried operation succeeded
+  pub fn reset(&mut self) {
+    self.current = self.initial;
+  }
+
+  /// Double the current pause without exceeding the maximum. A pause of zero grows to one micro second first.
+  fn advance(&mut self) {
+    if self.current.is_zero() {
+      self.current = Duration::from_micros(1).min(self.max);
+      return;
+    }
+    self.current = self
+      .current
+      .checked_mul(2)
+      .map_or(self.max, |next| next.min(self.max));
+  }
+}
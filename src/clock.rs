@@ -0,0 +1,315 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Time Source
+//!
+//! All time measurement of this crate is based on the tick count provided by a [TimeSource]. By
+//! default this is the free running counter of the system timer peripheral. With the feature ``mock-time`` and in
+//! the unit tests of this crate a different source can be plugged in with ``set_time_source``, e.g. a
+//! ``MockTimeSource`` to test timing dependent code without the real hardware. The examples of this crate that
+//! replace the time source require this feature.
+//!
+//! The default time source is chosen at compile time and read directly, so [now](crate::now) neither takes a lock
+//! nor dispatches dynamically. Only with a replaceable time source the active one is looked up with an atomic
+//! pointer.
+//!
+//! The tick count of a time source never decreases, so successive calls to [now](crate::now) on the same core
+//! never return a smaller value than a previous one. In debug builds [now](crate::now) checks this with an
+//! assertion against the last value seen on the current core. This catches e.g. a misconfigured peripheral base
+//! address that makes [now](crate::now) read garbage. The check is compiled out in release builds. Replacing
+//! the time source with ``set_time_source`` starts the check over.
+//!
+//! With the feature ``std`` the default time source is the [StdTimeSource] instead, that is based on the clock of
+//! the host. This allows crates building on this one to run their timing dependent tests on a development machine.
+//! Functions that access the timer peripherals directly, like [raw_ticks] or the scheduler interrupt, are not
+//! covered by this.
+//!
+
+use crate::interface::*;
+#[cfg(debug_assertions)]
+use crate::{core_id, critical::irq_masked, CORES};
+#[cfg(any(test, feature = "mock-time"))]
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicU64, Ordering};
+#[cfg(any(test, feature = "mock-time"))]
+use core::{ptr, sync::atomic::AtomicPtr};
+#[cfg(feature = "std")]
+use ruspiro_singleton::Singleton;
+
+/// A source of the current time given as ticks of a free running counter
+pub trait TimeSource: Sync {
+  /// The current tick count of the free running counter
+  fn ticks(&self) -> u64;
+}
+
+/// The [TimeSource] reading the free running counter of the system timer peripheral
+pub struct SystemTimeSource;
+
+impl TimeSource for SystemTimeSource {
+  fn ticks(&self) -> u64 {
+    raw_ticks()
+  }
+}
+
+/// The [TimeSource] counting the ticks since it has been read the first time with the clock of the host, only
+/// available with the feature ``std``. This is the default time source with this feature active.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// let start = std::time::Instant::now();
+/// sleep(Duration::from_millis(5));
+/// assert!(start.elapsed() >= Duration::from_millis(5));
+/// # }
+/// ```
+#[cfg(feature = "std")]
+pub struct StdTimeSource;
+
+/// The time of the host the [StdTimeSource] has been read the first time
+#[cfg(feature = "std")]
+static STD_START: Singleton<Option<std::time::Instant>> = Singleton::new(None);
+
+#[cfg(feature = "std")]
+impl TimeSource for StdTimeSource {
+  fn ticks(&self) -> u64 {
+    let start = STD_START.with_mut(|start| *start.get_or_insert_with(std::time::Instant::now));
+    crate::duration_to_ticks(start.elapsed())
+  }
+}
+
+/// Read the higher and lower 32Bit of the free running counter of the system timer as ``(chi, clo)``. This always
+/// reads the system timer peripheral, regardless of the active [TimeSource]. Reassembling both parts gives the same
+/// value as [raw_ticks].
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// let (hi, lo) = now_raw_split();
+/// let ticks = raw_ticks();
+/// // both are read one after another, so the later read is a bit ahead
+/// assert!(ticks >= ((hi as u64) << 32) | lo as u64);
+/// # }
+/// ```
+pub fn now_raw_split() -> (u32, u32) {
+  // the counter is read with two 32Bit accesses. If the lower part rolls over between those reads the
+  // combined value would be wrong, so read the higher part twice and repeat if it has changed
+  loop {
+    let t_high = SYS_TIMERCHI().get();
+    let t_low = SYS_TIMERCLO().get();
+    if SYS_TIMERCHI().get() == t_high {
+      return (t_high, t_low);
+    }
+  }
+}
+
+/// Read the free running counter of the system timer. This always reads the system timer peripheral, regardless of
+/// the active [TimeSource].
+pub fn raw_ticks() -> u64 {
+  let (t_high, t_low) = now_raw_split();
+  ((t_high as u64) << 32) | t_low as u64
+}
+
+/// Read the free running counter of the system timer like [raw_ticks] and encode it as little-endian 64Bit value,
+/// e.g. to timestamp a binary log record with 8 bytes.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// let bytes = raw_ticks_le_bytes();
+/// let ticks = u64::from_le_bytes(bytes);
+/// assert_eq!(Instant::from_ticks_le_bytes(bytes).to_ticks_le_bytes(), bytes);
+/// assert!(ticks <= raw_ticks());
+/// # }
+/// ```
+pub fn raw_ticks_le_bytes() -> [u8; 8] {
+  raw_ticks().to_le_bytes()
+}
+
+/// A [TimeSource] that only advances when told so. This allows to test timing dependent code deterministically. It
+/// is only available with the feature ``mock-time`` and in the unit tests of this crate.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// CLOCK.advance(1_000);
+/// assert_eq!(now(), Duration::from_micros(1_000));
+/// # }
+/// ```
+#[cfg(any(test, feature = "mock-time"))]
+pub struct MockTimeSource {
+  ticks: AtomicU64,
+}
+
+#[cfg(any(test, feature = "mock-time"))]
+impl MockTimeSource {
+  /// Create a new [MockTimeSource] starting at the given tick count
+  pub const fn new(ticks: u64) -> Self {
+    Self {
+      ticks: AtomicU64::new(ticks),
+    }
+  }
+
+  /// Set the current tick count
+  pub fn set(&self, ticks: u64) {
+    self.ticks.store(ticks, Ordering::Release);
+  }
+
+  /// Advance the current tick count by the given amount of ticks
+  pub fn advance(&self, ticks: u64) {
+    self.ticks.fetch_add(ticks, Ordering::AcqRel);
+  }
+}
+
+#[cfg(any(test, feature = "mock-time"))]
+impl TimeSource for MockTimeSource {
+  fn ticks(&self) -> u64 {
+    self.ticks.load(Ordering::Acquire)
+  }
+}
+
+/// The time source used for all time measurements unless it has been replaced
+#[cfg(not(feature = "std"))]
+const DEFAULT_TIME_SOURCE: SystemTimeSource = SystemTimeSource;
+
+/// The time source used for all time measurements unless it has been replaced
+#[cfg(feature = "std")]
+const DEFAULT_TIME_SOURCE: StdTimeSource = StdTimeSource;
+
+/// The time source replacing the default one, null as long as the default one is used. A reference to a trait
+/// object is too wide to be swapped atomically, so this points to a leaked box holding the reference.
+#[cfg(any(test, feature = "mock-time"))]
+static TIME_SOURCE: AtomicPtr<&'static dyn TimeSource> = AtomicPtr::new(ptr::null_mut());
+
+/// The last tick count seen by [checked_ticks] on each core
+#[cfg(debug_assertions)]
+static LAST_TICKS: [AtomicU64; CORES] = [
+  AtomicU64::new(0),
+  AtomicU64::new(0),
+  AtomicU64::new(0),
+  AtomicU64::new(0),
+];
+
+/// Replace the [TimeSource] used for all time measurements of this crate. This is only available with the feature
+/// ``mock-time`` and in the unit tests of this crate, so the clock can not be swapped in production code.
+///
+/// Another core might still read the previous source while it is replaced, so the few bytes referring to it are
+/// never released. Replacing the time source is meant for tests only anyway.
+#[cfg(any(test, feature = "mock-time"))]
+pub fn set_time_source(source: &'static dyn TimeSource) {
+  TIME_SOURCE.store(Box::into_raw(Box::new(source)), Ordering::Release);
+  // the new source may start at any tick count
+  #[cfg(debug_assertions)]
+  for last in LAST_TICKS.iter() {
+    last.store(0, Ordering::Release);
+  }
+}
+
+/// The current tick count of the active [TimeSource]
+pub(crate) fn ticks() -> u64 {
+  #[cfg(any(test, feature = "mock-time"))]
+  {
+    let source = TIME_SOURCE.load(Ordering::Acquire);
+    if !source.is_null() {
+      // this is safe as a time source that has been set is never released
+      return unsafe { (*source).ticks() };
+    }
+  }
+  DEFAULT_TIME_SOURCE.ticks()
+}
+
+/// The current tick count of the active [TimeSource], asserted not to be smaller than the last one seen on the current
+/// core. The counter is read and compared with the IRQ masked. Otherwise the timer interrupt could read the time in
+/// between, so a valid but older read would look like the time went backwards.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// // a corrupted read far in the future followed by the valid one
+/// CLOCK.set(u64::MAX / 2);
+/// now();
+/// CLOCK.set(1_000);
+/// // this panics in debug builds
+/// now();
+/// # }
+/// ```
+#[cfg(debug_assertions)]
+pub(crate) fn checked_ticks() -> u64 {
+  let (ticks, last) = irq_masked(|| {
+    let ticks = ticks();
+    (
+      ticks,
+      LAST_TICKS[core_id()].fetch_max(ticks, Ordering::AcqRel),
+    )
+  });
+  debug_assert!(
+    ticks >= last,
+    "time went backwards from {} to {} ticks, is the peripheral base configured correctly?",
+    last,
+    ticks
+  );
+
+  ticks
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    now,
+    testing::{setup, CLOCK},
+    Duration,
+  };
+
+  #[test]
+  fn now_follows_time_source() {
+    let _guard = setup();
+    assert_eq!(now(), Duration::from_micros(0));
+    CLOCK.advance(1_500);
+    assert_eq!(now(), Duration::from_micros(1_500));
+    // reading the same tick count twice is fine
+    assert_eq!(now(), Duration::from_micros(1_500));
+  }
+
+  #[test]
+  #[cfg(debug_assertions)]
+  #[should_panic(expected = "time went backwards")]
+  fn corrupted_then_valid_read_trips_assertion() {
+    let _guard = setup();
+    // a corrupted read far in the future followed by the valid one
+    CLOCK.set(u64::MAX / 2);
+    now();
+    CLOCK.set(1_000);
+    now();
+  }
+
+  #[test]
+  fn replacing_time_source_restarts_check() {
+    let _guard = setup();
+    CLOCK.set(u64::MAX / 2);
+    now();
+    CLOCK.set(1_000);
+    set_time_source(&CLOCK);
+    assert_eq!(now(), Duration::from_micros(1_000));
+  }
+
+  #[test]
+  #[cfg(feature = "std")]
+  fn sleep_follows_host_clock() {
+    let _guard = setup();
+    set_time_source(&StdTimeSource);
+    let host_start = std::time::Instant::now();
+    let start = now();
+
+    crate::sleep(Duration::from_millis(5));
+    assert!(now() - start >= Duration::from_millis(5));
+    assert!(host_start.elapsed() >= Duration::from_millis(5));
+  }
+}
@@ -0,0 +1,226 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Compare Channels
+//!
+//! Direct access to the compare channels of the system timer, e.g. to drive an own interrupt handler while still
+//! using this crate for [now](crate::now). The system timer has 4 compare channels, but the channels 0 and 2 are
+//! used by the GPU firmware and should be avoided. Therefore [Channel] only offers the channels 1 and 3.
+//!
+//! Those channels are used by this crate as well. The scheduler owns channel 1, or channel 3 with the feature
+//! ``schedule-timer3`` active, and [sleep_low_power](crate::sleep_low_power) uses channel 3 otherwise. So a channel
+//! need to be claimed with [claim] before it can be armed. The channel owned by the scheduler is never handed out
+//! and each other channel only to one owner at a time, [sleep_low_power](crate::sleep_low_power) falls back to a
+//! busy wait while channel 3 is claimed. The channel is released once the [ClaimedChannel] is dropped.
+//!
+//! # Example
+//! ```no_run
+//! # use ruspiro_timer::*;
+//! # fn doc() {
+//! use compare::Channel;
+//!
+//! let channel = compare::claim(Channel::C3).expect("channel 3 in use");
+//! channel.clear();
+//! channel.arm(now() + Duration::from_millis(1));
+//! assert!(!channel.matched());
+//! sleep(Duration::from_millis(2));
+//! assert!(channel.matched());
+//! channel.clear();
+//! # }
+//! ```
+
+use crate::interface::*;
+use crate::{duration_to_ticks, Duration};
+use core::sync::atomic::{AtomicU8, Ordering};
+#[cfg(feature = "ruspiro-interrupt")]
+use ruspiro_interrupt::Interrupt;
+
+/// The flags of the channels that are currently claimed, see [Channel::claim_flag]
+static CLAIMED: AtomicU8 = AtomicU8::new(0);
+
+/// The compare channels of the system timer that are not used by the GPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+  /// Compare channel 1 raising the ``SystemTimer1`` interrupt
+  C1,
+  /// Compare channel 3 raising the ``SystemTimer3`` interrupt
+  C3,
+}
+
+impl Channel {
+  /// The interrupt raised when this channel matches the free running counter. This is only available with the
+  /// optional ``ruspiro-interrupt`` dependency.
+  #[cfg(feature = "ruspiro-interrupt")]
+  pub fn interrupt(self) -> Interrupt {
+    match self {
+      Channel::C1 => Interrupt::SystemTimer1,
+      Channel::C3 => Interrupt::SystemTimer3,
+    }
+  }
+
+  /// The match flag of this channel within the control/status register
+  fn match_flag(self) -> u32 {
+    match self {
+      Channel::C1 => SYS_TIMERCS::M1::MATCH.raw_value(),
+      Channel::C3 => SYS_TIMERCS::M3::MATCH.raw_value(),
+    }
+  }
+
+  /// The flag of this channel within [CLAIMED]
+  fn claim_flag(self) -> u8 {
+    match self {
+      Channel::C1 => 0b01,
+      Channel::C3 => 0b10,
+    }
+  }
+
+  /// Check whether this channel is owned by the scheduler with the current features
+  fn owned_by_scheduler(self) -> bool {
+    match self {
+      Channel::C1 => cfg!(all(feature = "schedule", not(feature = "schedule-timer3"))),
+      Channel::C3 => cfg!(feature = "schedule-timer3"),
+    }
+  }
+}
+
+/// Error returned from [claim] if the channel is owned by the scheduler or has already been claimed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelInUse;
+
+/// Exclusive access to a compare channel that has been claimed with [claim]. The channel is released once this is
+/// dropped, an armed compare value and the match flag are left as they are.
+#[derive(Debug)]
+pub struct ClaimedChannel {
+  channel: Channel,
+}
+
+impl ClaimedChannel {
+  /// The channel claimed
+  pub fn channel(&self) -> Channel {
+    self.channel
+  }
+
+  /// Arm the channel to match at the given time, as free running counter value like returned by [now](crate::now).
+  /// The compare register only matches the lower 32Bit of the counter, so the time should not be more than ~71
+  /// minutes ahead. A time that has already passed only matches after the lower 32Bit of the counter wrapped around.
+  pub fn arm(&self, at: Duration) {
+    let value = duration_to_ticks(at) as u32;
+    match self.channel {
+      Channel::C1 => SYS_TIMERC1().set(value),
+      Channel::C3 => SYS_TIMERC3().set(value),
+    }
+  }
+
+  /// Clear the match flag of the channel. This also acknowledges the pending interrupt of this channel.
+  pub fn clear(&self) {
+    // writing 1 clears the flag, the flags of the other channels are not touched by writing 0
+    SYS_TIMERCS().set(self.channel.match_flag());
+  }
+
+  /// Check whether the channel has matched the free running counter since its match flag has been cleared
+  pub fn matched(&self) -> bool {
+    SYS_TIMERCS().get() & self.channel.match_flag() != 0
+  }
+}
+
+impl Drop for ClaimedChannel {
+  fn drop(&mut self) {
+    CLAIMED.fetch_and(!self.channel.claim_flag(), Ordering::AcqRel);
+  }
+}
+
+/// Claim the exclusive access to the given compare channel. Returns [ChannelInUse] if the channel is owned by the
+/// scheduler with the current features or is already claimed, e.g. by [sleep_low_power](crate::sleep_low_power)
+/// halting a different core.
+pub fn claim(channel: Channel) -> Result<ClaimedChannel, ChannelInUse> {
+  if channel.owned_by_scheduler() {
+    return Err(ChannelInUse);
+  }
+  let flag = channel.claim_flag();
+  if CLAIMED.fetch_or(flag, Ordering::AcqRel) & flag != 0 {
+    return Err(ChannelInUse);
+  }
+
+  Ok(ClaimedChannel { channel })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::setup;
+
+  /// A channel not owned by the scheduler with the current features
+  fn free_channel() -> Channel {
+    if cfg!(feature = "schedule-timer3") {
+      Channel::C1
+    } else {
+      Channel::C3
+    }
+  }
+
+  #[test]
+  #[cfg(feature = "schedule")]
+  fn claim_rejects_channel_of_scheduler() {
+    let _guard = setup();
+    let channel = if cfg!(feature = "schedule-timer3") {
+      Channel::C3
+    } else {
+      Channel::C1
+    };
+
+    assert_eq!(claim(channel).unwrap_err(), ChannelInUse);
+  }
+
+  #[test]
+  fn claim_is_exclusive_until_dropped() {
+    let _guard = setup();
+    let claimed = claim(free_channel()).unwrap();
+    assert_eq!(claimed.channel(), free_channel());
+    assert_eq!(claim(free_channel()).unwrap_err(), ChannelInUse);
+
+    drop(claimed);
+    assert!(claim(free_channel()).is_ok());
+  }
+
+  #[test]
+  fn arm_sets_compare_register_of_channel() {
+    let _guard = setup();
+    let claimed = claim(free_channel()).unwrap();
+
+    claimed.arm(Duration::from_micros(0x1_0000_1234));
+    let (armed, other) = match free_channel() {
+      Channel::C1 => (SYS_TIMERC1().get(), SYS_TIMERC3().get()),
+      Channel::C3 => (SYS_TIMERC3().get(), SYS_TIMERC1().get()),
+    };
+    assert_eq!(armed, 0x1234);
+    assert_eq!(other, 0);
+  }
+
+  #[test]
+  fn clear_writes_only_match_flag_of_channel() {
+    let _guard = setup();
+    let claimed = claim(free_channel()).unwrap();
+
+    claimed.clear();
+    assert_eq!(SYS_TIMERCS().get(), free_channel().match_flag());
+  }
+
+  #[test]
+  fn matched_checks_only_match_flag_of_channel() {
+    let _guard = setup();
+    let claimed = claim(free_channel()).unwrap();
+    let other = match free_channel() {
+      Channel::C1 => Channel::C3,
+      Channel::C3 => Channel::C1,
+    };
+
+    SYS_TIMERCS().set(other.match_flag());
+    assert!(!claimed.matched());
+    SYS_TIMERCS().set(other.match_flag() | free_channel().match_flag());
+    assert!(claimed.matched());
+  }
+}
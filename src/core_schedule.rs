@@ -0,0 +1,177 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Per-Core Schedules
+//!
+//! Each core of the Raspberry Pi owns a separate list of scheduled functions. Functions scheduled for a core are
+//! only executed by this core while it calls [poll_core_schedules], so workloads that are pinned to a core do not
+//! contend with the other cores on the global schedules of [schedule](crate::schedule()).
+//!
+//! The system timer provides only two compare channels that are not used by the GPU. Channel 1 is used by the
+//! global scheduler and channel 3 by [sleep_low_power](crate::sleep_low_power) or, with the feature
+//! ``schedule-timer3`` active, by the global scheduler. There is no channel left to give each of the 4 cores an
+//! interrupt of its own, and the system timer interrupts are routed to a single core anyway. Therefore the per-core
+//! lists are not interrupt driven, each core has to poll its own list, e.g. from its main loop.
+//!
+//! # Example
+//! ```no_run
+//! # use ruspiro_timer::*;
+//! # fn doc() {
+//! // two independent lists for the cores 1 and 2
+//! schedule_on_core(1, Duration::from_millis(10), || println!("core 1"));
+//! schedule_on_core(2, Duration::from_millis(20), || println!("core 2"));
+//! assert_eq!(pending_on_core(1), 1);
+//! assert_eq!(pending_on_core(2), 1);
+//!
+//! // called on core 1 this only executes the function of its own list
+//! sleep(Duration::from_millis(30));
+//! poll_core_schedules();
+//! assert_eq!(pending_on_core(1), 0);
+//! assert_eq!(pending_on_core(2), 1);
+//! # }
+//! ```
+//!
+
+use crate::*;
+use alloc::{boxed::Box, collections::BTreeMap};
+use ruspiro_singleton::*;
+
+type ScheduledFunction = Box<dyn FnOnce() + 'static + Send>;
+
+/// The list of functions scheduled for one core sorted by their due time and the sequence number given to them, that
+/// tells apart the functions due at the same time. As the BTreeMap's new function is not a const one it is wrapped
+/// with an Option. The second value is the sequence number given to the next function.
+struct CoreSchedules(Option<BTreeMap<(Duration, u64), ScheduledFunction>>, u64);
+
+// the stored functions are only accessed with mutual exclusive access to the list
+unsafe impl Sync for CoreSchedules {}
+
+/// The lists of scheduled functions, one for each core
+static CORE_SCHEDULES: [Singleton<CoreSchedules>; CORES] = [
+  Singleton::new(CoreSchedules(None, 0)),
+  Singleton::new(CoreSchedules(None, 0)),
+  Singleton::new(CoreSchedules(None, 0)),
+  Singleton::new(CoreSchedules(None, 0)),
+];
+
+/// Schedule a function for delayed execution on the given core. The function is executed by this core the first
+/// time it calls [poll_core_schedules] after the delay has passed.
+///
+/// # Panics
+/// Panics if ``core`` is not the number of a core of the Raspberry Pi, e.g. 0 to 3.
+pub fn schedule_on_core<F>(core: usize, delay: Duration, function: F)
+where
+  F: FnOnce() + 'static + Send,
+{
+  assert!(core < CORES, "invalid core {}", core);
+  let function: ScheduledFunction = Box::new(function);
+  let due = now_plus(delay);
+  CORE_SCHEDULES[core].with_mut(|schedules| {
+    let sequence = schedules.1;
+    schedules.1 += 1;
+    schedules
+      .0
+      .get_or_insert_with(BTreeMap::new)
+      .insert((due, sequence), function);
+  });
+}
+
+/// Schedule a function for delayed execution on the current core, see [schedule_on_core]
+pub fn schedule_local<F>(delay: Duration, function: F)
+where
+  F: FnOnce() + 'static + Send,
+{
+  schedule_on_core(core_id(), delay, function);
+}
+
+/// Execute all functions scheduled for the current core that are due. The access to the list is released while a
+/// function is executed, so it may schedule new functions for any core.
+pub fn poll_core_schedules() {
+  let schedules = &CORE_SCHEDULES[core_id()];
+  loop {
+    let function = schedules.with_mut(|schedules| {
+      let list = schedules.0.as_mut()?;
+      let key = *list.keys().next()?;
+      if !is_due(key.0) {
+        return None;
+      }
+      list.remove(&key)
+    });
+    match function {
+      Some(function) => (function)(),
+      None => return,
+    }
+  }
+}
+
+/// The number of functions scheduled for the given core that have not been executed yet
+///
+/// # Panics
+/// Panics if ``core`` is not the number of a core of the Raspberry Pi, e.g. 0 to 3.
+pub fn pending_on_core(core: usize) -> usize {
+  assert!(core < CORES, "invalid core {}", core);
+  CORE_SCHEDULES[core].with_ref(|schedules| schedules.0.as_ref().map_or(0, BTreeMap::len))
+}
+
+/// Drop the functions scheduled for all cores, so each unit test starts with empty lists
+#[cfg(test)]
+pub(crate) fn clear_core_schedules() {
+  for schedules in CORE_SCHEDULES.iter() {
+    schedules.with_mut(|schedules| schedules.0 = None);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{setup, CLOCK};
+  use alloc::sync::Arc;
+  use core::sync::atomic::{AtomicUsize, Ordering};
+
+  #[test]
+  fn each_core_polls_only_its_own_list() {
+    let _guard = setup();
+    let local = core_id();
+    let other = (local + 1) % CORES;
+    let fired = Arc::new([AtomicUsize::new(0), AtomicUsize::new(0)]);
+    for (list, core) in [(0, local), (1, other)] {
+      let fired = Arc::clone(&fired);
+      schedule_on_core(core, Duration::from_millis(10), move || {
+        fired[list].fetch_add(1, Ordering::AcqRel);
+      });
+    }
+    assert_eq!(pending_on_core(local), 1);
+    assert_eq!(pending_on_core(other), 1);
+
+    CLOCK.advance(10_000);
+    poll_core_schedules();
+    assert_eq!(fired[0].load(Ordering::Acquire), 1);
+    assert_eq!(fired[1].load(Ordering::Acquire), 0);
+    assert_eq!(pending_on_core(local), 0);
+    assert_eq!(pending_on_core(other), 1);
+  }
+
+  #[test]
+  fn local_functions_are_executed_in_order() {
+    let _guard = setup();
+    let order = Arc::new(AtomicUsize::new(0));
+    for (delay, id) in [(20, 2), (10, 1)] {
+      let order = Arc::clone(&order);
+      schedule_local(Duration::from_millis(delay), move || {
+        // each function is executed after the ones due earlier
+        assert_eq!(order.fetch_add(1, Ordering::AcqRel), id - 1);
+      });
+    }
+
+    CLOCK.advance(15_000);
+    poll_core_schedules();
+    assert_eq!(order.load(Ordering::Acquire), 1);
+    CLOCK.advance(5_000);
+    poll_core_schedules();
+    assert_eq!(order.load(Ordering::Acquire), 2);
+  }
+}
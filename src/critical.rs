@@ -0,0 +1,38 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # IRQ Masked Critical Sections
+//!
+//! The locks of ``ruspiro_singleton`` spin until they are released, but do not mask the interrupts while they are
+//! held. If an interrupt is raised on a core while the code it interrupted holds a lock the interrupt handler needs
+//! as well, the handler waits for a lock that is never released and the core is stuck. Therefore a lock shared
+//! with an interrupt handler is only taken with the IRQ of the current core masked, which is what [irq_masked]
+//! provides. The interrupt handlers themselves are executed with the IRQ masked anyway.
+//!
+
+#[cfg(all(target_arch = "aarch64", target_os = "none"))]
+use core::arch::asm;
+
+/// Run the given function with the IRQ masked on the current core. The previous mask is restored afterwards, so this
+/// can be nested and is also safe to be called from within an interrupt handler. The function should be short, as
+/// the interrupts of the current core are delayed until it has returned.
+///
+/// On hosted targets, like the unit tests of this crate, the code runs in user mode that can neither mask the IRQ
+/// nor be interrupted by the timer interrupt, so the function is just called.
+pub(crate) fn irq_masked<R>(f: impl FnOnce() -> R) -> R {
+  #[cfg(all(target_arch = "aarch64", target_os = "none"))]
+  {
+    let daif: u64;
+    // this is safe as only the IRQ mask of the current core is changed and it is restored afterwards
+    unsafe { asm!("mrs {}, daif", "msr daifset, #2", out(reg) daif) };
+    let result = f();
+    unsafe { asm!("msr daif, {}", in(reg) daif) };
+    result
+  }
+  #[cfg(not(all(target_arch = "aarch64", target_os = "none")))]
+  f()
+}
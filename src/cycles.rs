@@ -0,0 +1,114 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # CPU Cycle Conversion
+//!
+//! Convert between CPU cycles and real time based on a known CPU frequency. This complements
+//! [sleepcycles](crate::sleepcycles) to reason about cycle counts in real time. All conversions use 128Bit
+//! intermediate values so even large cycle counts do not overflow. Results exceeding the target type saturate.
+//!
+//! # Example
+//! ```no_run
+//! # use ruspiro_timer::*;
+//! # fn doc() {
+//! let cycles = cycles::duration_to_cycles(Duration::from_micros(1), 1_200_000_000);
+//! assert_eq!(cycles, 1_200);
+//! assert_eq!(cycles::cycles_to_duration(cycles, 1_200_000_000), Duration::from_micros(1));
+//! # }
+//! ```
+
+use crate::Duration;
+#[cfg(all(target_arch = "aarch64", target_os = "none"))]
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// The CPU frequency of the Raspberry Pi 3 used until a different one is set with [set_cpu_frequency]
+const DEFAULT_CPU_HZ: u64 = 1_200_000_000;
+
+/// The CPU frequency used by [to_duration] and [from_duration]
+static CPU_HZ: AtomicU64 = AtomicU64::new(DEFAULT_CPU_HZ);
+
+/// Convert the given amount of CPU cycles into the time they take at the given CPU frequency in Hz
+pub fn cycles_to_duration(cycles: u64, cpu_hz: u64) -> Duration {
+  let nanos = cycles as u128 * 1_000_000_000 / cpu_hz.max(1) as u128;
+  let secs = nanos / 1_000_000_000;
+  if secs > u64::MAX as u128 {
+    return Duration::MAX;
+  }
+
+  Duration::new(secs as u64, (nanos % 1_000_000_000) as u32)
+}
+
+/// Convert the given duration into the amount of CPU cycles executed in this time at the given CPU frequency in Hz
+pub fn duration_to_cycles(d: Duration, cpu_hz: u64) -> u64 {
+  let cycles = d
+    .as_nanos()
+    .checked_mul(cpu_hz as u128)
+    .map_or(u128::MAX, |product| product / 1_000_000_000);
+  cycles.min(u64::MAX as u128) as u64
+}
+
+/// Set the CPU frequency in Hz used by [to_duration] and [from_duration]. It defaults to the 1.2GHz of the
+/// Raspberry Pi 3 and should be updated whenever the CPU frequency is changed.
+pub fn set_cpu_frequency(hz: u64) {
+  CPU_HZ.store(hz, Ordering::Release);
+}
+
+/// Get the CPU frequency in Hz that has been set with [set_cpu_frequency]
+pub fn cpu_frequency() -> u64 {
+  CPU_HZ.load(Ordering::Acquire)
+}
+
+/// Read the cycle counter ``PMCCNTR_EL0`` of the performance monitors unit of the current core. Returns ``None`` if
+/// the PMU or its cycle counter has not been enabled, which need to be done by the program with the bit ``E`` of
+/// ``PMCR_EL0`` and the bit ``C`` of ``PMCNTENSET_EL0``. When running at EL0 the access to the PMU need to be granted
+/// with ``PMUSERENR_EL0`` as well.
+///
+/// Outside of bare metal, e.g. on an aarch64 Linux host, this always returns ``None`` as the access is not granted
+/// there and reading the PMU register would trap. The callers fall back to the time source in this case.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// if let Some(start) = cycles::cycle_counter() {
+///   sleepcycles(1_000);
+///   println!("took {} cycles", cycles::cycle_counter().unwrap() - start);
+/// }
+/// # }
+/// ```
+pub fn cycle_counter() -> Option<u64> {
+  #[cfg(all(target_arch = "aarch64", target_os = "none"))]
+  {
+    let pmcr: u64;
+    let cntenset: u64;
+    // this is safe as reading the PMU registers has no side effects
+    unsafe {
+      asm!("mrs {}, pmcr_el0", out(reg) pmcr);
+      asm!("mrs {}, pmcntenset_el0", out(reg) cntenset);
+    }
+    // the counters are enabled with PMCR_EL0.E and the cycle counter with PMCNTENSET_EL0.C
+    if pmcr & 1 == 0 || cntenset & (1 << 31) == 0 {
+      return None;
+    }
+    let cycles: u64;
+    unsafe { asm!("mrs {}, pmccntr_el0", out(reg) cycles) };
+    Some(cycles)
+  }
+  #[cfg(not(all(target_arch = "aarch64", target_os = "none")))]
+  None
+}
+
+/// Convert the given amount of CPU cycles into the time they take at the CPU frequency set with
+/// [set_cpu_frequency]
+pub fn to_duration(cycles: u64) -> Duration {
+  cycles_to_duration(cycles, cpu_frequency())
+}
+
+/// Convert the given duration into the amount of CPU cycles at the CPU frequency set with [set_cpu_frequency]
+pub fn from_duration(d: Duration) -> u64 {
+  duration_to_cycles(d, cpu_frequency())
+}
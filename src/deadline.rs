@@ -0,0 +1,75 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Deadline
+//!
+//! An absolute point in time that can be checked from several places without re-deriving the target time.
+//!
+
+use crate::*;
+
+/// A deadline at a fixed [Instant]. This is the synchronous sibling of the ``Delay`` future of the ``async_timer``
+/// module and is handy for protocol state machines that need to check the same timeout in several places.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+  /// Create a deadline being reached after the given duration from now on
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_timer::*;
+  /// # fn doc() {
+  /// let deadline = Deadline::after(Duration::from_millis(100));
+  /// while !deadline.is_elapsed() {
+  ///   // wait for the response of a peripheral ...
+  /// }
+  /// # }
+  /// ```
+  pub fn after(duration: Duration) -> Self {
+    Self(Instant(now_plus(duration)))
+  }
+
+  /// Create a deadline being reached at the given [Instant]
+  pub fn at(instant: Instant) -> Self {
+    Self(instant)
+  }
+
+  /// The [Instant] this deadline is reached at
+  pub fn instant(&self) -> Instant {
+    self.0
+  }
+
+  /// The time left until the deadline is reached. Returns ``None`` if the deadline has already passed.
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_timer::*;
+  /// # fn doc() {
+  /// let deadline = Deadline::after(Duration::ZERO);
+  /// assert!(deadline.is_elapsed());
+  /// assert_eq!(deadline.remaining(), None);
+  /// # }
+  /// ```
+  pub fn remaining(&self) -> Option<Duration> {
+    let remaining = (self.0).0.checked_sub(now())?;
+    if remaining.is_zero() {
+      None
+    } else {
+      Some(remaining)
+    }
+  }
+
+  /// Returns true if the deadline has been reached
+  pub fn is_elapsed(&self) -> bool {
+    is_due((self.0).0)
+  }
+
+  /// Pause the current execution until the deadline has been reached. This returns immediately if the deadline has
+  /// already passed.
+  pub fn sleep_remaining(&self) {
+    sleep_until((self.0).0);
+  }
+}
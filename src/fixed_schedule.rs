@@ -0,0 +1,164 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Fixed Capacity Schedules
+//!
+//! Scheduler that never allocates, available with the feature ``heapless``. The functions are stored in a static
+//! array of [FIXED_SCHEDULE_CAPACITY] slots as plain function pointer together with a context value passed to it.
+//! Scheduling more functions than there are free slots fails with [ScheduleFull](crate::ScheduleFull). Like the
+//! per-core schedules the functions are executed by calling [poll_fixed_schedules] regularly, as the compare channels
+//! are already used by the regular scheduler and [sleep_low_power](crate::sleep_low_power).
+//!
+//! Neither this scheduler nor [sleep](crate::sleep) and [now](crate::now) allocate. However, the dependencies of this
+//! crate like ``ruspiro-interrupt`` link the ``alloc`` crate, so a global allocator still need to be registered.
+//! In images without a heap this can be an allocator that always fails, as it is never called by these functions.
+//!
+//! # Example
+//! ```no_run
+//! # use ruspiro_timer::*;
+//! fn blink(led: usize) {
+//!   println!("toggle led {}", led);
+//! }
+//!
+//! # fn doc() {
+//! schedule_fixed(Duration::from_millis(10), blink, 1).unwrap();
+//! schedule_fixed(Duration::from_millis(20), blink, 2).unwrap();
+//! loop {
+//!   poll_fixed_schedules();
+//!   // do other work ...
+//! #   break;
+//! }
+//! # }
+//! ```
+//!
+
+use crate::*;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+/// The maximum number of functions that can be pending at once
+pub const FIXED_SCHEDULE_CAPACITY: usize = 16;
+
+/// The slot is not used
+const FREE: u8 = 0;
+/// The slot has been claimed and is about to be written or read
+const BUSY: u8 = 1;
+/// The slot contains a pending function
+const PENDING: u8 = 2;
+
+/// A function scheduled with its context value
+#[derive(Clone, Copy)]
+struct FixedFunction {
+  function: fn(usize),
+  context: usize,
+}
+
+/// Storage for one scheduled function
+struct Slot {
+  state: AtomicU8,
+  /// The free running counter value the function is due at
+  due: AtomicU64,
+  function: UnsafeCell<Option<FixedFunction>>,
+}
+
+// The function is only accessed while the slot has been claimed with the atomic state, so sharing the slots between
+// cores is safe
+unsafe impl Sync for Slot {}
+
+impl Slot {
+  const fn new() -> Self {
+    Self {
+      state: AtomicU8::new(FREE),
+      due: AtomicU64::new(0),
+      function: UnsafeCell::new(None),
+    }
+  }
+}
+
+/// The slots of all functions scheduled with [schedule_fixed]
+static SLOTS: [Slot; FIXED_SCHEDULE_CAPACITY] = [
+  Slot::new(),
+  Slot::new(),
+  Slot::new(),
+  Slot::new(),
+  Slot::new(),
+  Slot::new(),
+  Slot::new(),
+  Slot::new(),
+  Slot::new(),
+  Slot::new(),
+  Slot::new(),
+  Slot::new(),
+  Slot::new(),
+  Slot::new(),
+  Slot::new(),
+  Slot::new(),
+];
+
+/// Schedule the function to be called with the given context value once the delay has passed. The function is
+/// executed with the first call to [poll_fixed_schedules] after it is due. Returns [ScheduleFull] if all
+/// [FIXED_SCHEDULE_CAPACITY] slots are in use.
+pub fn schedule_fixed(
+  delay: Duration,
+  function: fn(usize),
+  context: usize,
+) -> Result<(), ScheduleFull> {
+  let due = duration_to_ticks(now_plus(delay));
+  for slot in SLOTS.iter() {
+    if slot
+      .state
+      .compare_exchange(FREE, BUSY, Ordering::AcqRel, Ordering::Relaxed)
+      .is_ok()
+    {
+      unsafe { *slot.function.get() = Some(FixedFunction { function, context }) };
+      slot.due.store(due, Ordering::Release);
+      slot.state.store(PENDING, Ordering::Release);
+      return Ok(());
+    }
+  }
+
+  Err(ScheduleFull)
+}
+
+/// Execute all functions scheduled with [schedule_fixed] that are due, the earliest one first
+pub fn poll_fixed_schedules() {
+  loop {
+    let current = duration_to_ticks(now());
+    let earliest = SLOTS
+      .iter()
+      .filter(|slot| slot.state.load(Ordering::Acquire) == PENDING)
+      .map(|slot| (slot.due.load(Ordering::Acquire), slot))
+      .filter(|&(due, _)| due <= current)
+      .min_by_key(|&(due, _)| due);
+    let slot = match earliest {
+      Some((_, slot)) => slot,
+      None => return,
+    };
+    // claim the slot, if this fails a different core has taken the function in the meanwhile
+    if slot
+      .state
+      .compare_exchange(PENDING, BUSY, Ordering::AcqRel, Ordering::Relaxed)
+      .is_err()
+    {
+      continue;
+    }
+    let function = unsafe { (*slot.function.get()).take() };
+    // release the slot before calling the function, so it can schedule itself again
+    slot.state.store(FREE, Ordering::Release);
+    if let Some(FixedFunction { function, context }) = function {
+      function(context);
+    }
+  }
+}
+
+/// The number of functions scheduled with [schedule_fixed] that have not been executed yet
+pub fn pending_fixed_schedules() -> usize {
+  SLOTS
+    .iter()
+    .filter(|slot| slot.state.load(Ordering::Acquire) == PENDING)
+    .count()
+}
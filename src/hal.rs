@@ -0,0 +1,88 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # embedded-hal Support
+//!
+//! Implementations of the ``embedded-hal`` timer traits based on the system timer, available with the feature
+//! ``embedded-hal``. This allows drivers written against these traits to be used with this crate.
+//!
+
+use crate::*;
+use embedded_hal_02::timer::{CountDown, Periodic};
+use void::Void;
+
+/// A count down based on the system timer implementing the ``CountDown`` trait of ``embedded-hal``. The count down
+/// is periodic, once it has expired it restarts with the same duration. The next expiry is calculated from the
+/// previous one and not from the time [wait](CountDown::wait) has been called, so the count down does not drift.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::{*, hal::*};
+/// use embedded_hal_02::timer::CountDown;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// let mut countdown = SysCountDown::new();
+/// // a count down that has not been started never expires
+/// assert_eq!(countdown.wait(), Err(nb::Error::WouldBlock));
+///
+/// countdown.start(Duration::from_millis(10));
+/// CLOCK.advance(9_999);
+/// assert_eq!(countdown.wait(), Err(nb::Error::WouldBlock));
+/// CLOCK.advance(1);
+/// assert_eq!(countdown.wait(), Ok(()));
+/// // it restarts automatically with the same duration
+/// assert_eq!(countdown.wait(), Err(nb::Error::WouldBlock));
+/// CLOCK.advance(10_000);
+/// assert_eq!(countdown.wait(), Ok(()));
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct SysCountDown {
+  /// The duration of one period of the count down
+  period: Duration,
+  /// The time the count down expires next, if it has been started
+  deadline: Option<Duration>,
+}
+
+impl SysCountDown {
+  /// Create a count down that has not been started yet
+  pub const fn new() -> Self {
+    Self {
+      period: Duration::from_secs(0),
+      deadline: None,
+    }
+  }
+}
+
+impl CountDown for SysCountDown {
+  type Time = Duration;
+
+  /// Start the count down to expire once the given duration has passed. A count down that is already running is
+  /// restarted.
+  fn start<T>(&mut self, count: T)
+  where
+    T: Into<Self::Time>,
+  {
+    self.period = count.into();
+    self.deadline = Some(now_plus(self.period));
+  }
+
+  /// Returns ``Ok`` once the count down has expired and ``WouldBlock`` otherwise. A count down that has not been
+  /// started never expires.
+  fn wait(&mut self) -> nb::Result<(), Void> {
+    match self.deadline {
+      Some(deadline) if is_due(deadline) => {
+        self.deadline = Some(deadline + self.period);
+        Ok(())
+      }
+      _ => Err(nb::Error::WouldBlock),
+    }
+  }
+}
+
+impl Periodic for SysCountDown {}
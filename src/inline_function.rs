@@ -0,0 +1,89 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Inline Function Storage
+//!
+//! Storage of the functions passed to the scheduler that keeps small closures inline instead of boxing them. Most
+//! scheduled closures capture only a few values, like a flag or a counter, so this saves a heap allocation for each
+//! of them. Closures that do not fit into the inline storage are boxed and only the box is stored inline.
+//!
+
+use alloc::boxed::Box;
+use core::{
+  marker::PhantomData,
+  mem::{self, ManuallyDrop, MaybeUninit},
+  ptr,
+};
+
+/// The number of machine words a closure may occupy to be stored inline, 24 bytes on the 64Bit targets
+const INLINE_WORDS: usize = 3;
+
+type Storage = MaybeUninit<[usize; INLINE_WORDS]>;
+
+/// A function that can be called once, stored inline if it fits into [INLINE_WORDS] machine words and is not
+/// aligned stricter than a machine word, or boxed otherwise
+pub(crate) struct InlineFunction {
+  storage: Storage,
+  /// Call the function in the storage, this moves the function out of it
+  call: unsafe fn(*mut u8),
+  /// Drop the function in the storage without calling it
+  drop: unsafe fn(*mut u8),
+  /// Keep the auto traits of the boxed function this replaces, the storage itself would be ``Sync``
+  _marker: PhantomData<Box<dyn FnOnce() + 'static + Send>>,
+}
+
+impl InlineFunction {
+  /// Store the given function, inline if it fits and boxed otherwise
+  pub(crate) fn new<F: FnOnce() + 'static + Send>(function: F) -> Self {
+    if mem::size_of::<F>() <= mem::size_of::<Storage>()
+      && mem::align_of::<F>() <= mem::align_of::<Storage>()
+    {
+      Self::inline(function)
+    } else {
+      // a box of a sized function is a single pointer, so it always fits
+      Self::inline(Box::new(function))
+    }
+  }
+
+  /// Store the given function inline, the caller need to ensure it fits into the storage
+  fn inline<F: FnOnce() + 'static + Send>(function: F) -> Self {
+    let mut storage = Storage::uninit();
+    // this is safe as the storage is large enough and sufficiently aligned for the function
+    unsafe { ptr::write(storage.as_mut_ptr() as *mut F, function) };
+    Self {
+      storage,
+      call: call_stored::<F>,
+      drop: drop_stored::<F>,
+      _marker: PhantomData,
+    }
+  }
+
+  /// Call the stored function
+  pub(crate) fn call(self) {
+    // the function moves out of the storage when called, so it must not be dropped a second time afterwards
+    let mut this = ManuallyDrop::new(self);
+    unsafe { (this.call)(this.storage.as_mut_ptr() as *mut u8) };
+  }
+}
+
+impl Drop for InlineFunction {
+  fn drop(&mut self) {
+    // this is safe as a function that has been called is never dropped
+    unsafe { (self.drop)(self.storage.as_mut_ptr() as *mut u8) };
+  }
+}
+
+/// Move the function of type ``F`` out of the storage and call it
+unsafe fn call_stored<F: FnOnce()>(storage: *mut u8) {
+  let function = ptr::read(storage as *mut F);
+  function();
+}
+
+/// Drop the function of type ``F`` in the storage
+unsafe fn drop_stored<F: FnOnce()>(storage: *mut u8) {
+  ptr::drop_in_place(storage as *mut F);
+}
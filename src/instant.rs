@@ -0,0 +1,314 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Instant
+//!
+//! A point in time measured by the free running counter of the system timer.
+//!
+
+use crate::*;
+use core::ops::{Add, AddAssign};
+
+/// A point in time as value of the free running counter of the system timer
+///
+/// Instants are ordered by their plain counter value. The 64Bit counter of the system timer does not roll over
+/// within the lifetime of a board, so no wrapping needs to be considered and an earlier [Instant] is always the
+/// smaller one. This allows to keep instants in sorted collections.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # extern crate alloc;
+/// # use alloc::collections::BTreeSet;
+/// # fn doc() {
+/// let first = Instant::now();
+/// let second = first + Duration::from_millis(10);
+/// assert!(first < second);
+/// assert_eq!(first + Duration::from_millis(10), second);
+///
+/// let mut deadlines = BTreeSet::new();
+/// deadlines.insert(second);
+/// deadlines.insert(first);
+/// assert_eq!(deadlines.iter().next(), Some(&first));
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(pub(crate) Duration);
+
+impl Instant {
+  /// Get the [Instant] for the current time
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_timer::*;
+  /// # fn doc() {
+  /// let start = Instant::now();
+  /// # }
+  /// ```
+  pub fn now() -> Self {
+    Self(now())
+  }
+
+  /// The time passed since this [Instant]
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_timer::*;
+  /// # fn doc() {
+  /// let start = Instant::now();
+  /// sleep(Duration::from_millis(10));
+  /// assert!(start.elapsed() >= Duration::from_millis(10));
+  /// # }
+  /// ```
+  pub fn elapsed(&self) -> Duration {
+    Self::now().duration_since(*self)
+  }
+
+  /// The whole milliseconds passed since this [Instant], saturating at ``u64::MAX``
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_timer::*;
+  /// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+  ///
+  /// # fn doc() {
+  /// set_time_source(&CLOCK);
+  /// let start = Instant::now();
+  /// CLOCK.advance(12_345);
+  /// assert_eq!(start.elapsed_ms(), 12);
+  /// assert_eq!(start.elapsed_us(), 12_345);
+  /// # }
+  /// ```
+  pub fn elapsed_ms(&self) -> u64 {
+    self.elapsed().as_millis().min(u64::MAX as u128) as u64
+  }
+
+  /// The whole microseconds passed since this [Instant], saturating at ``u64::MAX``, see [Instant::elapsed_ms]
+  pub fn elapsed_us(&self) -> u64 {
+    self.elapsed().as_micros().min(u64::MAX as u128) as u64
+  }
+
+  /// The time passed from the `earlier` [Instant] to this one. This returns a zero duration if `earlier` is
+  /// actually later than this one.
+  pub fn duration_since(&self, earlier: Instant) -> Duration {
+    self.since(earlier).unwrap_or_default()
+  }
+
+  /// The time passed from the `earlier` [Instant] to this one, saturating at a zero duration if `earlier` is
+  /// actually later than this one. This is the same as [Instant::duration_since] named like its counterpart of
+  /// ``std::time::Instant``.
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_timer::*;
+  /// # fn doc() {
+  /// let earlier = Instant::now();
+  /// let later = earlier + Duration::from_millis(10);
+  /// assert_eq!(later.saturating_duration_since(earlier), Duration::from_millis(10));
+  /// assert_eq!(earlier.saturating_duration_since(later), Duration::ZERO);
+  /// # }
+  /// ```
+  pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+    self.duration_since(earlier)
+  }
+
+  /// The time passed from the `earlier` [Instant] to this one. This returns ``None`` if `earlier` is actually later
+  /// than this one, e.g. because the order of both has been mixed up. Use [Instant::duration_since] to clamp this
+  /// case to a zero duration instead.
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_timer::*;
+  /// # fn doc() {
+  /// let earlier = Instant::now();
+  /// let later = earlier + Duration::from_millis(10);
+  /// assert_eq!(later.since(earlier), Some(Duration::from_millis(10)));
+  /// assert_eq!(earlier.since(later), None);
+  /// assert_eq!(earlier.duration_since(later), Duration::ZERO);
+  /// # }
+  /// ```
+  pub fn since(&self, earlier: Instant) -> Option<Duration> {
+    self.0.checked_sub(earlier.0)
+  }
+
+  /// Encode this [Instant] as the little-endian 64Bit tick count of the free running counter, e.g. for compact
+  /// binary logging. Sub tick precision is lost, so the encoding round-trips for every [Instant] read from the timer.
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_timer::*;
+  /// # fn doc() {
+  /// let start = Instant::now();
+  /// let bytes = start.to_ticks_le_bytes();
+  /// assert_eq!(Instant::from_ticks_le_bytes(bytes), start);
+  ///
+  /// // one second after the counter started
+  /// let bytes = [0x40, 0x42, 0x0f, 0, 0, 0, 0, 0];
+  /// let instant = Instant::from_ticks_le_bytes(bytes);
+  /// assert_eq!(instant.to_ticks_le_bytes(), 1_000_000u64.to_le_bytes());
+  /// # }
+  /// ```
+  pub fn to_ticks_le_bytes(&self) -> [u8; 8] {
+    self.as_ticks().to_le_bytes()
+  }
+
+  /// Decode an [Instant] from the little-endian 64Bit tick count created with [Instant::to_ticks_le_bytes] or
+  /// [raw_ticks_le_bytes]
+  pub fn from_ticks_le_bytes(bytes: [u8; 8]) -> Self {
+    Self::from_ticks(u64::from_le_bytes(bytes))
+  }
+
+  /// Create the [Instant] the free running counter of the system timer shows the given amount of ticks at
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_timer::*;
+  /// # fn doc() {
+  /// let start = Instant::now();
+  /// assert_eq!(Instant::from_ticks(start.as_ticks()), start);
+  /// assert_eq!(Instant::from_ticks(1_000_000).as_ticks(), 1_000_000);
+  /// assert_eq!(Duration::from(Instant::from_ticks(1_000_000)), Duration::from_secs(1));
+  /// # }
+  /// ```
+  pub fn from_ticks(ticks: u64) -> Self {
+    Self(ticks_to_duration(ticks))
+  }
+
+  /// The amount of ticks the free running counter of the system timer shows at this [Instant]. Sub tick precision is
+  /// lost.
+  pub fn as_ticks(&self) -> u64 {
+    duration_to_ticks(self.0)
+  }
+}
+
+/// Convert the time since boot as returned by [now] into an [Instant]
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// let since_boot = now();
+/// let instant = Instant::from(since_boot);
+/// assert_eq!(Duration::from(instant), since_boot);
+/// assert_eq!(Instant::from(Duration::from_micros(1_500)).as_ticks(), 1_500);
+/// # }
+/// ```
+impl From<Duration> for Instant {
+  fn from(since_boot: Duration) -> Self {
+    Self(since_boot)
+  }
+}
+
+/// Convert an [Instant] into the time since boot like it is returned by [now]
+impl From<Instant> for Duration {
+  fn from(instant: Instant) -> Self {
+    instant.0
+  }
+}
+
+/// Get the [Instant] the given duration later. The addition saturates like [now_plus], so an [Instant] beyond the range
+/// of ``Duration`` is the one at ``Duration::MAX`` that is never reached.
+impl Add<Duration> for Instant {
+  type Output = Instant;
+
+  fn add(self, rhs: Duration) -> Self::Output {
+    Self(self.0.checked_add(rhs).unwrap_or(Duration::MAX))
+  }
+}
+
+impl AddAssign<Duration> for Instant {
+  fn add_assign(&mut self, rhs: Duration) {
+    *self = *self + rhs;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{setup, CLOCK};
+
+  #[test]
+  fn add_saturates_at_max() {
+    let instant = Instant::from(Duration::from_secs(1));
+    assert_eq!(
+      instant + Duration::from_secs(1),
+      Instant::from(Duration::from_secs(2))
+    );
+    assert_eq!(instant + Duration::MAX, Instant::from(Duration::MAX));
+
+    let mut instant = instant;
+    instant += Duration::MAX;
+    assert_eq!(instant, Instant::from(Duration::MAX));
+  }
+
+  #[test]
+  fn since_is_none_for_later_instant() {
+    let earlier = Instant::from(Duration::from_secs(1));
+    let later = earlier + Duration::from_millis(10);
+
+    assert_eq!(later.since(earlier), Some(Duration::from_millis(10)));
+    assert_eq!(earlier.since(earlier), Some(Duration::ZERO));
+    assert_eq!(earlier.since(later), None);
+    assert_eq!(earlier.duration_since(later), Duration::ZERO);
+  }
+
+  #[test]
+  fn instants_are_ordered_and_hashed_by_their_time() {
+    use std::collections::{BTreeSet, HashSet};
+
+    let first = Instant::from(Duration::from_secs(1));
+    let second = first + Duration::from_nanos(1);
+    assert!(first < second);
+    assert_eq!(first.max(second), second);
+
+    let sorted: BTreeSet<_> = [second, first, second].iter().copied().collect();
+    assert_eq!(sorted.into_iter().collect::<Vec<_>>(), [first, second]);
+
+    let hashed: HashSet<_> = [first, second, Instant::from(Duration::from_secs(1))]
+      .iter()
+      .copied()
+      .collect();
+    assert_eq!(hashed.len(), 2);
+    assert!(hashed.contains(&first));
+  }
+
+  #[test]
+  fn saturating_duration_since_clamps_to_zero() {
+    let earlier = Instant::from(Duration::from_secs(1));
+    let later = earlier + Duration::from_millis(10);
+
+    assert_eq!(
+      later.saturating_duration_since(earlier),
+      Duration::from_millis(10)
+    );
+    assert_eq!(earlier.saturating_duration_since(later), Duration::ZERO);
+    assert_eq!(
+      Instant::from(Duration::ZERO).saturating_duration_since(Instant::from(Duration::MAX)),
+      Duration::ZERO
+    );
+  }
+
+  #[test]
+  fn elapsed_ms_and_us_are_whole_units() {
+    let _guard = setup();
+    let start = Instant::now();
+
+    CLOCK.advance(12_345);
+    assert_eq!(start.elapsed_ms(), 12);
+    assert_eq!(start.elapsed_us(), 12_345);
+  }
+
+  #[test]
+  fn elapsed_ms_and_us_of_future_instant_are_zero() {
+    let _guard = setup();
+    let future = Instant::now() + Duration::from_secs(1);
+
+    assert_eq!(future.elapsed_ms(), 0);
+    assert_eq!(future.elapsed_us(), 0);
+  }
+
+  #[test]
+  fn elapsed_us_reaches_counter_limit() {
+    let _guard = setup();
+    let start = Instant::now();
+
+    CLOCK.set(u64::MAX);
+    assert_eq!(start.elapsed_us(), u64::MAX);
+    assert_eq!(start.elapsed_ms(), u64::MAX / 1_000);
+  }
+}
@@ -8,7 +8,8 @@
 //! # Low-Level interface access to timer register
 //!
 
-use ruspiro_mmio_register::define_mmio_register;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use ruspiro_mmio_register::{define_mmio_register, ReadOnly, ReadWrite, WriteOnly};
 
 // MMIO peripheral base address based on the target family provided with the custom target config file.
 #[cfg(feature = "pi3")]
@@ -18,12 +19,177 @@ const PERIPHERAL_BASE: usize = 0x0_FE00_0000;
 #[cfg(feature = "pi4_high")]
 const PERIPHERAL_BASE: usize = 0x4_7E00_0000;
 
+// Offset of the system timer MMIO register from the peripheral base address
+const SYS_TIMER_OFFSET: usize = 0x3000;
+// Offset of the ARM timer MMIO register from the peripheral base address
+const ARM_TIMER_OFFSET: usize = 0xB000;
+
 // Base address of system timer MMIO register
 #[allow(dead_code)]
-const SYS_TIMER_BASE: usize = PERIPHERAL_BASE + 0x3000;
+const SYS_TIMER_BASE: usize = PERIPHERAL_BASE + SYS_TIMER_OFFSET;
 // Base address of ARM timer MMIO register
 #[allow(dead_code)]
-const ARM_TIMER_BASE: usize = PERIPHERAL_BASE + 0xB000;
+const ARM_TIMER_BASE: usize = PERIPHERAL_BASE + ARM_TIMER_OFFSET;
+
+/// The peripheral base address actually used to access the timer register. This defaults to the address selected
+/// with the board feature and can be changed with [init_peripheral_base] before the first register access.
+static RUNTIME_PERIPHERAL_BASE: AtomicUsize = AtomicUsize::new(PERIPHERAL_BASE);
+
+/// Flag indicating that the timer register has been accessed and the peripheral base address can no longer change
+static BASE_IN_USE: AtomicBool = AtomicBool::new(false);
+
+/// The frequency of the free running counter used for all conversions between ticks and time
+static TIMER_FREQUENCY: AtomicU64 = AtomicU64::new(crate::SYS_TIMER_HZ);
+
+/// Error returned from [init_peripheral_base] if the timer register has already been accessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeripheralBaseInUse;
+
+/// Set the MMIO peripheral base address used to access the timer register at runtime. This allows a single kernel
+/// image to support different boards by detecting the board (e.g. from the device tree or the board revision) and
+/// configuring the timer accordingly. Without calling this the base address selected with the ``pi3``, ``pi4_low``
+/// or ``pi4_high`` feature is used.
+///
+/// The base address can only be changed before the first access to the timer register, as for example with
+/// [now](crate::now). Any later call is rejected with [PeripheralBaseInUse]. Keep in mind that the
+/// [``ruspiro_interrupt`` crate](https://crates.io/crates/ruspiro_interrupt) still uses the base address selected
+/// with the feature.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// // configure the timer for a Raspberry Pi 4 in low peripheral mode
+/// init_peripheral_base(0xFE00_0000).expect("timer already in use");
+/// # }
+/// ```
+pub fn init_peripheral_base(base: usize) -> Result<(), PeripheralBaseInUse> {
+  if BASE_IN_USE.load(Ordering::Acquire) {
+    return Err(PeripheralBaseInUse);
+  }
+  RUNTIME_PERIPHERAL_BASE.store(base, Ordering::Release);
+  // the register might have been accessed from a different core in the meantime using the previous address
+  if BASE_IN_USE.load(Ordering::Acquire) {
+    return Err(PeripheralBaseInUse);
+  }
+
+  Ok(())
+}
+
+/// The configuration of the system timer peripheral that can be set with [configure]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerConfig {
+  /// The MMIO peripheral base address the system timer register are located relative to
+  pub base: usize,
+  /// The frequency the free running counter of the system timer is running at
+  pub frequency_hz: u64,
+}
+
+impl TimerConfig {
+  /// The configuration used without calling [configure], with the peripheral base address selected with the board
+  /// feature and the 1MHz of the Raspberry Pi system timer
+  pub const DEFAULT: Self = Self {
+    base: PERIPHERAL_BASE,
+    frequency_hz: crate::SYS_TIMER_HZ,
+  };
+}
+
+impl Default for TimerConfig {
+  fn default() -> Self {
+    Self::DEFAULT
+  }
+}
+
+/// Configure the peripheral base address and the frequency of the system timer at runtime, e.g. for boards or
+/// emulators that clock the free running counter differently. All conversions between ticks and time like
+/// [now](crate::now) or [duration_to_ticks](crate::duration_to_ticks) use the configured frequency. Without calling
+/// this the [TimerConfig::DEFAULT] is used.
+///
+/// Like with [init_peripheral_base] the configuration can only be changed before the first access to the timer
+/// register, any later call is rejected with [PeripheralBaseInUse] and keeps the current configuration.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+///
+/// # fn doc() {
+/// // a timer counting with 2MHz
+/// configure(TimerConfig {
+///   frequency_hz: 2_000_000,
+///   ..TimerConfig::DEFAULT
+/// })
+/// .expect("timer already in use");
+/// set_time_source(&CLOCK);
+/// CLOCK.advance(2_000_000);
+/// assert_eq!(now(), Duration::from_secs(1));
+/// assert_eq!(duration_to_ticks(Duration::from_millis(1)), 2_000);
+/// # }
+/// ```
+pub fn configure(config: TimerConfig) -> Result<(), PeripheralBaseInUse> {
+  if BASE_IN_USE.load(Ordering::Acquire) {
+    return Err(PeripheralBaseInUse);
+  }
+  init_peripheral_base(config.base)?;
+  TIMER_FREQUENCY.store(config.frequency_hz.max(1), Ordering::Release);
+
+  Ok(())
+}
+
+/// Get the frequency of the free running counter of the system timer, which is [SYS_TIMER_HZ](crate::SYS_TIMER_HZ)
+/// unless a different one has been set with [configure]
+pub fn timer_frequency() -> u64 {
+  TIMER_FREQUENCY.load(Ordering::Acquire)
+}
+
+/// Point the timer register to the memory at the given address and reset the configuration, so the unit tests can
+/// access mocked register and configure the timer again
+#[cfg(test)]
+pub(crate) fn use_mock_register(base: usize) {
+  RUNTIME_PERIPHERAL_BASE.store(base, Ordering::Release);
+  TIMER_FREQUENCY.store(crate::SYS_TIMER_HZ, Ordering::Release);
+  BASE_IN_USE.store(false, Ordering::Release);
+}
+
+/// Get the peripheral base address to be used to access the timer register and lock it against further changes
+fn peripheral_base() -> usize {
+  if !BASE_IN_USE.load(Ordering::Relaxed) {
+    BASE_IN_USE.store(true, Ordering::Release);
+  }
+  RUNTIME_PERIPHERAL_BASE.load(Ordering::Acquire)
+}
+
+// Provide the access to the timer register relative to the peripheral base address chosen at runtime. The accessor
+// functions are named like the register definitions below to be used as ``SYS_TIMERCS().get()`` while the field
+// definitions are still available with ``SYS_TIMERCS::M1::MATCH``.
+macro_rules! runtime_register {
+  ($($name:ident<$access:ident>@($offset:expr)),*) => {
+    $(
+      #[allow(non_snake_case, dead_code)]
+      #[inline]
+      pub fn $name() -> $access<u32> {
+        $access::<u32>::new(peripheral_base() + $offset)
+      }
+    )*
+  };
+}
+
+runtime_register![
+  SYS_TIMERCS<ReadWrite>@(SYS_TIMER_OFFSET),
+  SYS_TIMERCLO<ReadOnly>@(SYS_TIMER_OFFSET + 0x04),
+  SYS_TIMERCHI<ReadOnly>@(SYS_TIMER_OFFSET + 0x08),
+  SYS_TIMERC0<ReadWrite>@(SYS_TIMER_OFFSET + 0x0C),
+  SYS_TIMERC1<ReadWrite>@(SYS_TIMER_OFFSET + 0x10),
+  SYS_TIMERC2<ReadWrite>@(SYS_TIMER_OFFSET + 0x14),
+  SYS_TIMERC3<ReadWrite>@(SYS_TIMER_OFFSET + 0x18),
+  ARM_TIMERLOAD<ReadWrite>@(ARM_TIMER_OFFSET + 0x400),
+  ARM_TIMERVALUE<ReadOnly>@(ARM_TIMER_OFFSET + 0x404),
+  ARM_TIMERCTRL<ReadWrite>@(ARM_TIMER_OFFSET + 0x408),
+  ARM_TIMERACKN<WriteOnly>@(ARM_TIMER_OFFSET + 0x40C),
+  ARM_TIMERRAWIRQ<ReadOnly>@(ARM_TIMER_OFFSET + 0x410),
+  ARM_TIMERMASKIRQ<ReadOnly>@(ARM_TIMER_OFFSET + 0x414),
+  ARM_TIMERRELOAD<ReadWrite>@(ARM_TIMER_OFFSET + 0x418),
+  ARM_TIMERPREDIV<ReadWrite>@(ARM_TIMER_OFFSET + 0x41C),
+  ARM_TIMERFRCOUNTER<ReadOnly>@(ARM_TIMER_OFFSET + 0x420)
+];
 
 // Define the MMIO timer register
 define_mmio_register![
@@ -126,3 +292,47 @@ define_mmio_register![
     /// ARM timer free running counter value
     pub ARM_TIMERFRCOUNTER<ReadOnly<u32>@(ARM_TIMER_BASE + 0x420)>
 ];
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{raw_ticks, testing::setup, testing::REGISTERS};
+
+  #[test]
+  fn register_are_accessed_at_overridden_base() {
+    let _guard = setup();
+    init_peripheral_base(REGISTERS.base() + 0x100).unwrap();
+
+    SYS_TIMERC3().set(7);
+    assert_eq!(REGISTERS.read(0x100 + SYS_TIMER_OFFSET + 0x18), 7);
+    assert_eq!(REGISTERS.read(SYS_TIMER_OFFSET + 0x18), 0);
+  }
+
+  #[test]
+  fn counter_is_read_at_overridden_base() {
+    let _guard = setup();
+    init_peripheral_base(REGISTERS.base()).unwrap();
+    REGISTERS.write(SYS_TIMER_OFFSET + 0x04, 0x5678);
+    REGISTERS.write(SYS_TIMER_OFFSET + 0x08, 0x1234);
+
+    assert_eq!(raw_ticks(), 0x1234_0000_5678);
+  }
+
+  #[test]
+  fn base_is_locked_after_first_access() {
+    let _guard = setup();
+    init_peripheral_base(REGISTERS.base()).unwrap();
+    // changing the base is fine until the register are accessed
+    init_peripheral_base(REGISTERS.base()).unwrap();
+
+    SYS_TIMERC1().set(1);
+    assert_eq!(
+      init_peripheral_base(REGISTERS.base() + 0x100),
+      Err(PeripheralBaseInUse)
+    );
+    assert_eq!(configure(TimerConfig::default()), Err(PeripheralBaseInUse));
+    assert_eq!(REGISTERS.read(SYS_TIMER_OFFSET + 0x10), 1);
+    SYS_TIMERC1().set(2);
+    assert_eq!(REGISTERS.read(SYS_TIMER_OFFSET + 0x10), 2);
+  }
+}
@@ -0,0 +1,159 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Scheduler Latency Samples
+//!
+//! Opt-in recording of the time each scheduled function has been due and the time it has actually been executed.
+//! The last [LATENCY_SAMPLES] samples are kept in a ring buffer that is written lock free from the timer interrupt.
+//! While [schedule_stats](crate::schedule_stats) only provides the maximum lateness, the samples show the
+//! distribution of the latency, e.g. to profile soft real time systems.
+//!
+//! # Example
+//! ```no_run
+//! # use ruspiro_timer::*;
+//! # fn doc() {
+//! set_latency_sampling(true);
+//! schedule(Duration::from_millis(1), || ());
+//! schedule(Duration::from_millis(2), || ());
+//! sleep(Duration::from_millis(5));
+//! let mut count = 0;
+//! for sample in schedule_latency_samples() {
+//!   assert_eq!(sample.lateness, sample.actual - sample.due);
+//!   count += 1;
+//! }
+//! assert_eq!(count, 2);
+//! # }
+//! ```
+
+use crate::Duration;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// The number of latency samples kept
+pub const LATENCY_SAMPLES: usize = 32;
+
+/// The timing of a single execution of a scheduled function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencySample {
+  /// The time the function has been due
+  pub due: Duration,
+  /// The time the function has actually been executed
+  pub actual: Duration,
+  /// The time between the two
+  pub lateness: Duration,
+}
+
+/// Storage of one sample in the ring buffer. The sequence number is odd while the sample is written, so a reader
+/// can detect a sample that is overwritten while it is read.
+struct SampleSlot {
+  sequence: AtomicUsize,
+  due: AtomicU64,
+  actual: AtomicU64,
+}
+
+impl SampleSlot {
+  const fn new() -> Self {
+    Self {
+      sequence: AtomicUsize::new(0),
+      due: AtomicU64::new(0),
+      actual: AtomicU64::new(0),
+    }
+  }
+}
+
+/// Flag indicating whether the samples are recorded
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The number of samples recorded so far, the next sample is written to this index modulo the buffer size
+static RECORDED: AtomicUsize = AtomicUsize::new(0);
+
+/// The ring buffer of the latency samples
+static SAMPLES: [SampleSlot; LATENCY_SAMPLES] = [
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+  SampleSlot::new(),
+];
+
+/// Enable or disable the recording of latency samples. Recording is disabled by default as it adds a few atomic
+/// writes to the execution of each scheduled function.
+pub fn set_latency_sampling(enabled: bool) {
+  ENABLED.store(enabled, Ordering::Release);
+}
+
+/// Get the latest recorded latency samples, the oldest one first. A sample that is overwritten by the timer
+/// interrupt while it is read is skipped.
+pub fn schedule_latency_samples() -> impl Iterator<Item = LatencySample> {
+  let end = RECORDED.load(Ordering::Acquire);
+  let start = end.saturating_sub(LATENCY_SAMPLES);
+  (start..end).filter_map(read_sample)
+}
+
+/// Record a sample of a function that has been due at the given time and is executed at ``actual``
+pub(crate) fn record_sample(due: Duration, actual: Duration) {
+  if !ENABLED.load(Ordering::Acquire) {
+    return;
+  }
+  let index = RECORDED.fetch_add(1, Ordering::AcqRel);
+  let slot = &SAMPLES[index % LATENCY_SAMPLES];
+  slot.sequence.store(2 * index + 1, Ordering::Release);
+  slot.due.store(as_nanos(due), Ordering::Release);
+  slot.actual.store(as_nanos(actual), Ordering::Release);
+  slot.sequence.store(2 * index + 2, Ordering::Release);
+}
+
+/// Read the sample with the given index if it is still available and not written at the moment
+fn read_sample(index: usize) -> Option<LatencySample> {
+  let slot = &SAMPLES[index % LATENCY_SAMPLES];
+  let sequence = slot.sequence.load(Ordering::Acquire);
+  if sequence != 2 * index + 2 {
+    return None;
+  }
+  let due = Duration::from_nanos(slot.due.load(Ordering::Acquire));
+  let actual = Duration::from_nanos(slot.actual.load(Ordering::Acquire));
+  if slot.sequence.load(Ordering::Acquire) != sequence {
+    return None;
+  }
+
+  Some(LatencySample {
+    due,
+    actual,
+    lateness: actual.checked_sub(due).unwrap_or_default(),
+  })
+}
+
+/// The duration in nano seconds, saturating at the range of 64Bit
+fn as_nanos(d: Duration) -> u64 {
+  d.as_nanos().min(u64::MAX as u128) as u64
+}
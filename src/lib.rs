@@ -5,35 +5,213 @@
  * License: Apache License 2.0
  **************************************************************************************************/
 #![doc(html_root_url = "https://docs.rs/ruspiro-timer/||VERSION||")]
-#![cfg_attr(not(any(test, doctest)), no_std)]
+#![cfg_attr(not(any(test, doctest, feature = "std")), no_std)]
 //! # Timer functions
 //!
 //! This crate provides simple timing functions to pause the actual processing for a specific amount of time. The core
 //! pausing is called on will block.
 //!
-//! It is also possible to delay function/closure execution. This is based on system timer interrupts.
+//! It is also possible to delay function/closure execution. This is based on system timer interrupts or on
+//! regularly polling the due functions with [poll_schedules].
 //!
 //!
 //! # Features
-//! Feature       | Description
-//! --------------|------------------------------------------------------------------------------
-//! `ruspiro_pi3` | active to use the proper timer MMIO base memory address for Raspberry Pi 3 when accessing the system timer peripheral
+//! Feature             | Description
+//! --------------------|------------------------------------------------------------------------------
+//! `pi3`               | active to use the proper timer MMIO base memory address for Raspberry Pi 3 when accessing the system timer peripheral
+//! `pi4_low`           | active to use the proper timer MMIO base memory address for Raspberry Pi 4 in Low-Peripheral mode when accessing the system timer peripheral
+//! `pi4_high`          | active to use the proper timer MMIO base memory address for Raspberry Pi 4 in High-Peripheral mode when accessing the system timer peripheral
+//! `schedule`          | (default) provide the scheduler to execute functions after a delay
+//! `irq-schedule`      | (default) execute the scheduled functions from the system timer interrupt
+//! `ruspiro-interrupt` | use the interrupts for the ARM timer tick and to halt the core in ``sleep_low_power``, enabled by `irq-schedule`
+//! `async`             | provide the ``async_timer`` module with futures based timer functions
+//! `schedule-timer3`   | use the system timer compare channel 3 for the scheduler instead of channel 1
+//! `timing-wheel`      | use a hashed timing wheel instead of a sorted list to store the scheduled functions, which scales better with thousands of pending functions
+//! `heapless`          | provide a fixed capacity scheduler with ``schedule_fixed`` that never allocates
+//! `diagnostics`       | provide ``dump_schedules`` to write the pending scheduled functions for diagnostics
+//! `dispatch-hooks`    | provide ``set_pre_dispatch`` and ``set_post_dispatch`` to trace the execution of the scheduled functions
+//! `mock-time`         | provide the ``MockTimeSource`` and ``set_time_source`` to replace the clock in tests
+//! `std`               | base the time on the clock of the host, so code using this crate can be tested on a development machine
+//! `embedded-hal`      | provide the ``hal`` module implementing the ``embedded-hal`` timer traits
+//!
+//! Without the `schedule` feature neither the scheduler nor its interrupt handler are part of the crate, so an
+//! application can own the ``SystemTimer1`` interrupt itself and still use the pausing and time functions. The
+//! ``ruspiro-interrupt`` dependency is only used with the `irq-schedule` feature or if it is enabled explicitly.
+//! Without it no interrupt handler is registered at all, the ARM timer tick is not available and [sleep_low_power]
+//! falls back to a busy wait. The board features are only forwarded to the dependency if it is used.
+//!
+//! Exactly one of the board features need to be active. The Raspberry Pi 2 (v1.2) and the Raspberry Pi Zero 2 share
+//! the peripheral base address of the Raspberry Pi 3 and use the `pi3` feature. The Raspberry Pi 1 and the original
+//! Raspberry Pi Zero are not supported as their ARMv6 core can not run the aarch64 code this crate and its
+//! dependencies are built for. Boards with a different peripheral base can still be used by setting the address
+//! with [init_peripheral_base].
 //!
 
 #[cfg(not(any(feature = "pi3", feature = "pi4_low", feature = "pi4_high")))]
 compile_error!("Either feature \"pi3\", \"pi4_low\" or \"pi4_high\" must be enabled for this crate");
 
+#[cfg(any(
+  all(feature = "pi3", feature = "pi4_low"),
+  all(feature = "pi3", feature = "pi4_high"),
+  all(feature = "pi4_low", feature = "pi4_high")
+))]
+compile_error!(
+  "Only one of the features \"pi3\", \"pi4_low\" or \"pi4_high\" can be enabled for this crate"
+);
+
 extern crate alloc;
 
+pub mod arm_timer;
+#[cfg(feature = "async")]
+pub mod async_timer;
+pub mod backoff;
+mod clock;
+pub mod compare;
+mod core_schedule;
+#[cfg(any(feature = "schedule", debug_assertions))]
+mod critical;
+pub mod cycles;
+mod deadline;
+#[cfg(feature = "heapless")]
+mod fixed_schedule;
+#[cfg(feature = "embedded-hal")]
+pub mod hal;
+#[cfg(feature = "schedule")]
+mod inline_function;
+mod instant;
 mod interface;
+#[cfg(feature = "schedule")]
+mod latency;
+mod power;
+mod rate_limiter;
+#[cfg(feature = "schedule")]
 mod schedule;
-pub use schedule::schedule;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "timing-wheel")]
+mod timing_wheel;
+#[cfg(feature = "schedule")]
+pub mod watchdog;
+#[cfg(feature = "std")]
+pub use clock::StdTimeSource;
+pub use clock::{now_raw_split, raw_ticks, raw_ticks_le_bytes, SystemTimeSource, TimeSource};
+#[cfg(any(test, feature = "mock-time"))]
+pub use clock::{set_time_source, MockTimeSource};
+pub use core_schedule::{pending_on_core, poll_core_schedules, schedule_local, schedule_on_core};
+pub use deadline::Deadline;
+#[cfg(feature = "heapless")]
+pub use fixed_schedule::{
+  pending_fixed_schedules, poll_fixed_schedules, schedule_fixed, FIXED_SCHEDULE_CAPACITY,
+};
+pub use instant::Instant;
+pub use interface::{
+  configure, init_peripheral_base, timer_frequency, PeripheralBaseInUse, TimerConfig,
+};
+#[cfg(feature = "schedule")]
+pub use latency::{schedule_latency_samples, set_latency_sampling, LatencySample, LATENCY_SAMPLES};
+pub use power::sleep_low_power;
+pub use rate_limiter::RateLimiter;
+#[cfg(feature = "diagnostics")]
+pub use schedule::dump_schedules;
+#[cfg(feature = "schedule")]
+pub use schedule::{
+  cancel, clear_all_schedules, flush_due_schedules, max_schedule_delay, on_isr_overrun,
+  pause_scheduler, pending_schedule_count, poll_schedules, reschedule, reset_schedule_stats,
+  resume_scheduler, schedule, schedule_at, schedule_irq_ready, schedule_many,
+  schedule_periodic_aligned, schedule_repeating, schedule_scoped, schedule_stats,
+  schedule_with_result, set_coalesce_window, set_isr_budget, set_overrun_threshold,
+  set_register_access, set_schedule_capacity, shutdown_scheduler, spawn_after,
+  time_until_next_schedule, try_schedule, upcoming_schedules, JoinHandle, RegisterAccess,
+  ScheduleGuard, ScheduleHandle, ScheduleStats, SystemTimerAccess, MIN_SCHEDULE_DELAY,
+  MIN_SCHEDULE_MARGIN,
+};
+#[cfg(feature = "dispatch-hooks")]
+pub use schedule::{set_post_dispatch, set_pre_dispatch};
 
 pub use core::time::Duration; // re-export Duration for convinence when using this crate
-use interface::*;
+
+use core::hint::spin_loop;
+use core::ops::ControlFlow;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::Poll;
 use ruspiro_arch_aarch64::instructions::nop;
+use ruspiro_arch_aarch64::register::el1::mpidr_el1;
+
+/// The free running counter value (in micro seconds) that marks the user chosen zero point for [uptime]
+static EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// The number of CPU cycles as executed by [sleepcycles] that fit into one millisecond. This is 0 as long as
+/// [calibrate_cycles] has not been called.
+static CYCLES_PER_MILLI: AtomicU64 = AtomicU64::new(0);
+
+/// The minimum time the calibration of the CPU cycles shall take to get a reasonable accuracy
+const CALIBRATION_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The frequency of the free running counter of the system timer of the Raspberry Pi. A different frequency can be
+/// set with [configure].
+pub const SYS_TIMER_HZ: u64 = 1_000_000;
+
+/// Error indicating that an operation did not complete in the given time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut;
+
+/// Error returned if a function can not be scheduled as the capacity of the scheduler has been reached, e.g. all
+/// slots of ``schedule_fixed`` are in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduleFull;
+
+/// Error returned by ``try_schedule`` if a function could not be scheduled
+#[cfg(feature = "schedule")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleError {
+  /// The capacity of the scheduler set with ``set_schedule_capacity`` has been reached
+  Full,
+  /// The delay is shorter than ``MIN_SCHEDULE_DELAY`` or longer than ``max_schedule_delay``
+  DelayOutOfRange,
+}
+
+#[cfg(feature = "schedule")]
+impl From<ScheduleFull> for ScheduleError {
+  fn from(_: ScheduleFull) -> Self {
+    ScheduleError::Full
+  }
+}
+
+/// The number of cores of the Raspberry Pi
+pub(crate) const CORES: usize = 4;
+
+/// Error returned by [self_test] or [try_now] if the system timer does not behave like a real free running counter.
+/// This typically indicates that the peripheral base address does not match the board, e.g. the Low-Peripheral
+/// address of the Raspberry Pi 4 is used while it runs in High-Peripheral mode, so the counter is read from a wrong
+/// address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerError {
+  /// The counter did not advance at all while the CPU was busy for a while
+  NotAdvancing,
+  /// The counter went backwards or jumped ahead much further than the time the CPU was busy
+  Implausible,
+}
+
+/// The number of ticks of the system timer [try_now] keeps the CPU busy between reading the counter. A working counter
+/// advances at least once within two ticks, even if it was about to advance at the first reading.
+const TRY_NOW_TICKS: u64 = 2;
+
+/// The number of CPU cycles [self_test] keeps the CPU busy between reading the counter
+const SELF_TEST_CYCLES: u64 = 100_000;
+
+/// The maximum number of ticks [self_test] accepts to pass while the CPU is busy. This is very generous as the self
+/// test is typically run during bring-up with the caches disabled, which slows down the busy loop heavily.
+const SELF_TEST_MAX_TICKS: u64 = 100_000;
 
 /// Pause the current execution for the given amount of micro seconds
+///
+/// The deadline is calculated with saturating arithmetic, so this never panics. Durations that would exceed the
+/// range of the 64Bit micro second counter, like ``Duration::MAX``, pause the execution forever.
+///
+/// The deadline is not truncated to the resolution of the counter. Any non-zero duration below one tick, like 500ns,
+/// therefore waits until the counter has advanced at least once, which is between a fraction of a tick and a whole
+/// tick. Pauses shorter than a tick that need to be more precise can be done with
+/// [sleepcycles]. Only a zero duration returns right away.
 /// # Example
 /// ```no_run
 /// # use ruspiro_timer::*;
@@ -42,13 +220,397 @@ use ruspiro_arch_aarch64::instructions::nop;
 /// sleep(Duration::from_secs(1));
 /// # }
 /// ```
+/// A nanosecond still waits for the next tick:
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicU64, Ordering};
+/// // a time source advancing one tick with every 10th read
+/// struct Slow(AtomicU64);
+///
+/// impl TimeSource for Slow {
+///   fn ticks(&self) -> u64 {
+///     self.0.fetch_add(1, Ordering::AcqRel) / 10
+///   }
+/// }
+///
+/// static CLOCK: Slow = Slow(AtomicU64::new(0));
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// let start = now();
+/// sleep(Duration::from_nanos(1));
+/// assert!(now() - start >= Duration::from_micros(1));
+/// # }
+/// ```
 pub fn sleep(duration: Duration) {
-  let wait_until = now() + duration;
+  sleep_with(duration, SpinStrategy::Hint);
+}
+
+/// How [sleep_with] waits for the duration to pass. The strategies trade the latency of noticing that the time is
+/// due against the power the core draws while waiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpinStrategy {
+  /// Read the counter in a tight loop. This notices the deadline with the lowest latency, but keeps the core and the
+  /// bus to the peripheral as busy as possible.
+  Tight,
+  /// Give the core the ``spin_loop`` hint between reading the counter, like [sleep] does. The latency is nearly the
+  /// same as with [SpinStrategy::Tight], but a core sharing its resources gets the chance to make progress.
+  Hint,
+  /// Halt the core until the system timer raises an interrupt at the deadline, like [sleep_low_power] does. This
+  /// draws the least power, but waking up adds the latency of the interrupt and the interrupts need to be set up.
+  /// With the ``ruspiro-interrupt`` dependency the core is woken up by the interrupt of the actual system timer, so
+  /// this only works on the hardware and not with a replaced time source. Without it this spins like
+  /// [SpinStrategy::Hint].
+  LowPower,
+}
+
+/// Pause the current execution for the given duration with the given [SpinStrategy], e.g. a real-time driver picks
+/// [SpinStrategy::Tight] while an idle loop picks [SpinStrategy::LowPower]. [sleep] uses [SpinStrategy::Hint].
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicU64, Ordering};
+/// // a time source advancing 1µs with each read
+/// struct Ticking(AtomicU64);
+///
+/// impl TimeSource for Ticking {
+///   fn ticks(&self) -> u64 {
+///     self.0.fetch_add(1, Ordering::AcqRel)
+///   }
+/// }
+///
+/// static CLOCK: Ticking = Ticking(AtomicU64::new(0));
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// for strategy in [SpinStrategy::Tight, SpinStrategy::Hint] {
+///   let deadline = now() + Duration::from_micros(100);
+///   sleep_with(Duration::from_micros(100), strategy);
+///   assert!(now() >= deadline);
+/// }
+/// # }
+/// ```
+/// The low power strategy relies on the system timer interrupt to wake up the core:
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// let deadline = now() + Duration::from_millis(1);
+/// sleep_with(Duration::from_millis(1), SpinStrategy::LowPower);
+/// assert!(now() >= deadline);
+/// # }
+/// ```
+pub fn sleep_with(duration: Duration, strategy: SpinStrategy) {
+  match strategy {
+    SpinStrategy::Tight => {
+      let wait_until = now_plus(duration);
+      while !is_due(wait_until) {}
+    }
+    SpinStrategy::Hint => {
+      let wait_until = now_plus(duration);
+      while !is_due(wait_until) {
+        spin_loop();
+      }
+    }
+    SpinStrategy::LowPower => sleep_low_power(duration),
+  }
+}
+
+/// The outcome of [sleep_cancellable]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepOutcome {
+  /// The whole duration has passed
+  Completed,
+  /// The pause has been cancelled before the duration has passed
+  Cancelled,
+}
+
+/// Pause the current execution for the given duration like [sleep], but return early once the ``cancel`` flag is set,
+/// e.g. from a different core or an interrupt handler to react to an error condition. The flag is checked before
+/// the time, so a flag that is already set cancels the pause right away. The flag is not reset.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+/// static CANCEL: AtomicBool = AtomicBool::new(false);
+///
+/// // a time source advancing 1µs with each read that sets the flag after 500µs like another core would do
+/// struct Canceller(AtomicU64);
+///
+/// impl TimeSource for Canceller {
+///   fn ticks(&self) -> u64 {
+///     let ticks = self.0.fetch_add(1, Ordering::AcqRel);
+///     if ticks == 500 {
+///       CANCEL.store(true, Ordering::Release);
+///     }
+///     ticks
+///   }
+/// }
+///
+/// static CLOCK: Canceller = Canceller(AtomicU64::new(0));
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// assert_eq!(sleep_cancellable(Duration::from_millis(1), &CANCEL), SleepOutcome::Cancelled);
+/// assert!(now() < Duration::from_millis(1));
+///
+/// CANCEL.store(false, Ordering::Release);
+/// assert_eq!(sleep_cancellable(Duration::from_micros(100), &CANCEL), SleepOutcome::Completed);
+/// # }
+/// ```
+pub fn sleep_cancellable(duration: Duration, cancel: &AtomicBool) -> SleepOutcome {
+  let wait_until = now_plus(duration);
+
+  loop {
+    if cancel.load(Ordering::Acquire) {
+      return SleepOutcome::Cancelled;
+    }
+    if is_due(wait_until) {
+      return SleepOutcome::Completed;
+    }
+    spin_loop();
+  }
+}
+
+/// Give the core a hint that the current execution is waiting, e.g. within a polling loop. This is the blocking
+/// counterpart of a zero-duration sleep and returns without any measurable delay. Within async tasks use
+/// ``async_timer::yield_now`` to give control back to the executor instead.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn peripheral_ready() -> bool { true }
+/// # fn doc() {
+/// while !peripheral_ready() {
+///   yield_now();
+/// }
+/// # }
+/// ```
+pub fn yield_now() {
+  spin_loop();
+}
+
+/// Pause the current execution for the given amount of milliseconds, see [sleep]
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// sleep_ms(10);
+/// # }
+/// ```
+pub fn sleep_ms(ms: u64) {
+  sleep(Duration::from_millis(ms));
+}
+
+/// Pause the current execution for the given amount of micro seconds, see [sleep]
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// sleep_us(150);
+/// # }
+/// ```
+pub fn sleep_us(us: u64) {
+  sleep(Duration::from_micros(us));
+}
+
+/// Pause the current execution for the given amount of seconds, see [sleep]
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// sleep_secs(1);
+/// # }
+/// ```
+pub fn sleep_secs(s: u64) {
+  sleep(Duration::from_secs(s));
+}
+
+/// Pause the current execution for the ``base`` duration plus a random jitter of less than ``max_jitter``, e.g. to
+/// keep several cores or devices retrying on a contended bus from doing so in lockstep. The jitter is taken in micro
+/// seconds as ``rng() % max_jitter``, so a ``max_jitter`` below one micro second does not add any jitter. The random
+/// number generator is passed as closure, which allows to use the hardware RNG without this crate depending on it.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// // a fixed "random" number of 1_234 gives a jitter of 234µs
+/// let mut rng = || 1_234;
+/// let (_, elapsed) = measure(|| sleep_jittered(Duration::from_millis(1), Duration::from_millis(1), &mut rng));
+/// assert!(elapsed >= Duration::from_micros(1_234));
+/// assert!(elapsed < Duration::from_millis(2));
+/// # }
+/// ```
+pub fn sleep_jittered(base: Duration, max_jitter: Duration, rng: &mut impl FnMut() -> u32) {
+  let max_jitter = max_jitter.as_micros().min(u64::MAX as u128) as u64;
+  let jitter = if max_jitter == 0 {
+    0
+  } else {
+    rng() as u64 % max_jitter
+  };
+  sleep(base.saturating_add(Duration::from_micros(jitter)));
+}
+
+/// Pause the current execution until the given point in time, as free running counter value like returned by
+/// [now], has been reached. If this time has already passed the function returns immediately.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// let start = now();
+/// // do some work and continue exactly 10ms after start, regardless how long the work took
+/// sleep_until(start + Duration::from_millis(10));
+/// # }
+/// ```
+pub fn sleep_until(deadline: Duration) {
+  while now() < deadline {
+    spin_loop();
+  }
+}
 
-  while !is_due(wait_until) {}
+/// Poll the given predicate until it returns true or the timeout has passed. This is the typical pattern of waiting
+/// for a hardware flag to be set without hanging forever if the hardware does not respond. On success the time it
+/// took until the predicate returned true is returned, [TimedOut] otherwise. The predicate is checked at least once,
+/// even with a timeout of zero.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// // the flag is set after 3 polls, each taking 10µs
+/// let mut polls = 0;
+/// let elapsed = wait_for(
+///   || {
+///     CLOCK.advance(10);
+///     polls += 1;
+///     polls == 3
+///   },
+///   Duration::from_micros(100),
+/// );
+/// assert_eq!(elapsed, Ok(Duration::from_micros(30)));
+///
+/// // the flag is never set
+/// assert_eq!(wait_for(|| { CLOCK.advance(10); false }, Duration::from_micros(100)), Err(TimedOut));
+/// # }
+/// ```
+pub fn wait_for<F: FnMut() -> bool>(
+  mut predicate: F,
+  timeout: Duration,
+) -> Result<Duration, TimedOut> {
+  let start = now();
+  let deadline = start.saturating_add(timeout);
+  loop {
+    if predicate() {
+      return Ok(now().saturating_sub(start));
+    }
+    if is_due(deadline) {
+      return Err(TimedOut);
+    }
+    spin_loop();
+  }
+}
+
+/// Run a piece of work that is split into steps until it completes or the deadline has passed. The step function is
+/// called repeatedly as long as it returns ``Poll::Pending`` and the deadline, a value of [now], is not due. This
+/// gives synchronous code a cooperative timeout without the need of an executor. The step is always called at least
+/// once, even if the deadline has passed already, and a step that is running when the deadline passes is not
+/// interrupted, so each step should only take a fraction of the time to the deadline.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::task::Poll;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// // the work is done in the 3rd step, each taking 10µs
+/// let mut chunks = 0;
+/// let result = run_until_deadline(now_plus(Duration::from_micros(100)), || {
+///   CLOCK.advance(10);
+///   chunks += 1;
+///   if chunks == 3 {
+///     Poll::Ready(chunks)
+///   } else {
+///     Poll::Pending
+///   }
+/// });
+/// assert_eq!(result, Ok(3));
+///
+/// // work that never completes times out at the deadline
+/// let deadline = now_plus(Duration::from_micros(100));
+/// let result: Result<(), _> = run_until_deadline(deadline, || {
+///   CLOCK.advance(10);
+///   Poll::Pending
+/// });
+/// assert_eq!(result, Err(TimedOut));
+/// assert_eq!(now(), deadline);
+/// # }
+/// ```
+pub fn run_until_deadline<F: FnMut() -> Poll<R>, R>(
+  deadline: Duration,
+  mut step: F,
+) -> Result<R, TimedOut> {
+  loop {
+    if let Poll::Ready(result) = step() {
+      return Ok(result);
+    }
+    if is_due(deadline) {
+      return Err(TimedOut);
+    }
+  }
+}
+
+/// Call the given function periodically with the given period until it returns ``ControlFlow::Break``. The first
+/// call happens immediately and the n-th call is due at ``start + n * period``. As each deadline is calculated
+/// from the start time the time spent inside the function does not add up to a drift of the average rate. If a call
+/// takes longer than the period the missed calls are skipped and the next call happens at the next future
+/// multiple of the period.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::ops::ControlFlow;
+/// # fn doc() {
+/// let mut blinks = 0;
+/// every(Duration::from_millis(500), || {
+///   blinks += 1;
+///   // toggle the LED here ...
+///   if blinks < 10 {
+///     ControlFlow::Continue(())
+///   } else {
+///     ControlFlow::Break(())
+///   }
+/// });
+/// # }
+/// ```
+pub fn every<F: FnMut() -> ControlFlow<()>>(period: Duration, mut f: F) {
+  let mut deadline = now();
+  loop {
+    if let ControlFlow::Break(()) = f() {
+      return;
+    }
+
+    deadline = deadline.saturating_add(period);
+    let current = now();
+    if deadline < current && period > Duration::from_secs(0) {
+      // the function overran at least one period, skip to the next boundary that is still in the future
+      let missed = (current - deadline).as_nanos() / period.as_nanos() + 1;
+      let skip = period
+        .checked_mul(missed.min(u32::MAX as u128) as u32)
+        .unwrap_or(Duration::MAX);
+      deadline = deadline.saturating_add(skip);
+    }
+    sleep_until(deadline);
+  }
 }
 
 /// Pause the current execution for the given amount of CPU cycles
+///
+/// Each cycle executes a single ``nop`` instruction. In contrast to the ``spin_loop`` hint used while waiting in
+/// [sleep] a ``nop`` has a fixed timing that does not depend on the hints the core or the other cores act on,
+/// which keeps the calibration with [calibrate_cycles] stable.
+///
+/// This is a best-effort busy loop. Besides the ``nop`` each iteration executes the loop counter and branch, so
+/// the actual number of CPU cycles passed depends on the code generated, the branch prediction and the caches, and
+/// the time it takes depends on the current CPU clock. Use [sleepcycles_at_least] if at least the given amount of
+/// CPU cycles need to pass.
 /// # Example
 /// ```no_run
 /// # use ruspiro_timer::*;
@@ -56,13 +618,206 @@ pub fn sleep(duration: Duration) {
 /// sleepcycles(1_000);
 /// # }
 /// ```
-pub fn sleepcycles(cycles: u32) {
+pub fn sleepcycles(cycles: u64) {
   for _ in 0..cycles {
     nop();
   }
 }
 
+/// Pause the current execution until at least the given amount of CPU cycles has passed. The cycles are counted with
+/// the cycle counter of the performance monitors unit if it has been enabled, see [cycles::cycle_counter].
+/// Otherwise the time the cycles take at the CPU frequency set with [cycles::set_cpu_frequency] is waited for with
+/// the free running counter of the system timer, extended by one tick as the current tick might almost be over. This is
+/// always the case outside of bare metal, where the cycle counter is not accessible.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// if let Some(start) = cycles::cycle_counter() {
+///   sleepcycles_at_least(10_000);
+///   assert!(cycles::cycle_counter().unwrap() - start >= 10_000);
+/// }
+/// # }
+/// ```
+pub fn sleepcycles_at_least(cycles: u64) {
+  match cycles::cycle_counter() {
+    Some(start) => loop {
+      match cycles::cycle_counter() {
+        Some(current) if current.wrapping_sub(start) < cycles => spin_loop(),
+        // the cycles have passed or the cycle counter has been disabled in the meanwhile
+        _ => return,
+      }
+    },
+    None => sleep(cycles::to_duration(cycles).saturating_add(ticks_to_duration(1))),
+  }
+}
+
+/// The number of the current core
+pub(crate) fn core_id() -> usize {
+  mpidr_el1::get() as usize & (CORES - 1)
+}
+
+/// Check that the system timer behaves like a real free running counter. The counter is read twice with a short
+/// busy loop in between, it need to advance in this time but must not jump ahead by more than 100ms. This is a quick
+/// sanity check while porting to a board, as a wrong peripheral base address does not fail but reads garbage.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicU64, Ordering};
+/// // a time source advancing by 10µs with each read like a working counter
+/// struct Ticking(AtomicU64);
+///
+/// impl TimeSource for Ticking {
+///   fn ticks(&self) -> u64 {
+///     self.0.fetch_add(10, Ordering::AcqRel)
+///   }
+/// }
+///
+/// static TICKING: Ticking = Ticking(AtomicU64::new(0));
+/// // a time source that never changes like a misconfigured peripheral
+/// static STALLED: MockTimeSource = MockTimeSource::new(0);
+///
+/// # fn doc() {
+/// set_time_source(&TICKING);
+/// assert_eq!(self_test(), Ok(()));
+/// set_time_source(&STALLED);
+/// assert_eq!(self_test(), Err(TimerError::NotAdvancing));
+/// # }
+/// ```
+pub fn self_test() -> Result<(), TimerError> {
+  // use the raw tick count, so a counter going backwards is reported as error instead of tripping the debug
+  // assertion of [now]
+  let first = clock::ticks();
+  sleepcycles(SELF_TEST_CYCLES);
+  let second = clock::ticks();
+  match second.checked_sub(first) {
+    Some(0) => Err(TimerError::NotAdvancing),
+    Some(ticks) if ticks <= SELF_TEST_MAX_TICKS => Ok(()),
+    _ => Err(TimerError::Implausible),
+  }
+}
+
+/// Get the current time like [now], but validate that the counter is actually working. The counter is read twice
+/// with a short busy loop of at least two ticks of the system timer in between. The counter under test can not time
+/// this loop, so its length is derived from the [cpu_frequency](cycles::cpu_frequency), which must not be set above
+/// the actual CPU frequency. If both reads give the same value the
+/// counter is stuck and [TimerError::NotAdvancing] is returned, if the second one is smaller
+/// [TimerError::Implausible]. Otherwise the second reading is returned. This allows bring-up code to tell a timer
+/// that is not working from time that just has not advanced, at the cost of the busy loop. A peripheral base address
+/// is always present as it defaults to the one of the board feature, so a wrong one shows up as a stuck or
+/// implausible counter as well.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(1_000);
+///
+/// # fn doc() {
+/// // the mock clock only advances when told so, like a stuck counter
+/// set_time_source(&CLOCK);
+/// assert_eq!(try_now(), Err(TimerError::NotAdvancing));
+/// set_time_source(&SystemTimeSource);
+/// assert!(try_now().is_ok());
+/// # }
+/// ```
+pub fn try_now() -> Result<Duration, TimerError> {
+  let first = clock::ticks();
+  // each iteration of the busy loop takes at least one CPU cycle
+  sleepcycles(cycles::from_duration(ticks_to_duration(TRY_NOW_TICKS)));
+  let second = clock::ticks();
+  match second.checked_sub(first) {
+    Some(0) => Err(TimerError::NotAdvancing),
+    Some(_) => Ok(ticks_to_duration(second)),
+    None => Err(TimerError::Implausible),
+  }
+}
+
+/// Measure how many CPU cycles as executed by [sleepcycles] fit into a known interval of the system timer. The
+/// resulting ratio is used by [sleep_cycles_calibrated]. The calibration takes a few milliseconds and need to be
+/// run once before [sleep_cycles_calibrated] is used. It should be re-run if the CPU frequency changes.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// calibrate_cycles();
+/// # }
+/// ```
+pub fn calibrate_cycles() {
+  let mut cycles: u64 = 1_000;
+  loop {
+    let (_, elapsed) = measure(|| sleepcycles(cycles));
+    if elapsed >= CALIBRATION_INTERVAL || cycles > u64::MAX / 2 {
+      let per_milli = cycles as u128 * 1_000_000 / elapsed.as_nanos().max(1);
+      CYCLES_PER_MILLI.store(per_milli as u64, Ordering::Release);
+      return;
+    }
+    cycles *= 2;
+  }
+}
+
+/// Pause the current execution for the given duration by executing the amount of CPU cycles that has been
+/// measured to fit into this time with [calibrate_cycles]. This allows waits shorter than the micro second
+/// resolution of the system timer. If the calibration has not been run this falls back to [sleep].
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// calibrate_cycles();
+/// // pause for 250 nano seconds
+/// sleep_cycles_calibrated(Duration::from_nanos(250));
+/// # }
+/// ```
+pub fn sleep_cycles_calibrated(duration: Duration) {
+  let per_milli = CYCLES_PER_MILLI.load(Ordering::Acquire);
+  if per_milli == 0 {
+    sleep(duration);
+    return;
+  }
+
+  let mut cycles = duration.as_nanos() * per_milli as u128 / 1_000_000;
+  while cycles > 0 {
+    let chunk = cycles.min(u64::MAX as u128);
+    sleepcycles(chunk as u64);
+    cycles -= chunk;
+  }
+}
+
+/// Pause the current execution for the given duration with a resolution below the tick of the system timer, one
+/// micro second at the default [timer_frequency]. The whole ticks are waited for using the system timer and the
+/// remaining nano seconds are spent in a loop of CPU cycles as measured with [calibrate_cycles], which need to be run
+/// once before.
+///
+/// The pause is at least as long as requested, but may take up to one tick longer as the current counter value of
+/// the system timer could already be almost a tick old when this function is called. The sub tick part has a jitter
+/// of a few CPU cycles and is affected by interrupts and cache effects.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// calibrate_cycles();
+/// let (_, elapsed) = measure(|| sleep_precise(Duration::from_nanos(1500)));
+/// assert!(elapsed >= Duration::from_nanos(1500));
+/// # }
+/// ```
+pub fn sleep_precise(duration: Duration) {
+  if duration.is_zero() {
+    return;
+  }
+
+  // the whole ticks are never longer than the duration as both conversions round down
+  let whole_ticks = ticks_to_duration(duration_to_ticks(duration));
+  let remainder = duration - whole_ticks;
+  // wait for one additional tick to ensure at least the whole ticks have passed
+  let wait_until = now()
+    .saturating_add(whole_ticks)
+    .saturating_add(ticks_to_duration(1));
+  sleep_until(wait_until);
+  sleep_cycles_calibrated(remainder);
+}
+
 /// Get the current time as free running counter value of the system timer
+///
+/// Successive calls on the same core never return a smaller value. Debug builds assert this to catch a time source
+/// returning garbage, e.g. due to a wrong peripheral base address. Release builds skip this check.
 /// # Example
 /// ```no_run
 /// # use ruspiro_timer::*;
@@ -71,30 +826,822 @@ pub fn sleepcycles(cycles: u32) {
 /// # }
 /// ```
 pub fn now() -> Duration {
-  let t_low = SYS_TIMERCLO::Register.get() as u64;
-  let t_high = SYS_TIMERCHI::Register.get() as u64;
+  #[cfg(debug_assertions)]
+  let ticks = clock::checked_ticks();
+  #[cfg(not(debug_assertions))]
+  let ticks = clock::ticks();
+  ticks_to_duration(ticks)
+}
+
+/// Get the time the given duration after now. The addition saturates, so a duration that would exceed the range of
+/// the free running counter gives ``Duration::MAX``, a point in time that is never reached. All relative deadlines
+/// of this crate are calculated this way.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(1_000);
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// assert_eq!(now_plus(Duration::from_micros(10)), Duration::from_micros(1_010));
+/// assert_eq!(now_plus(Duration::MAX), Duration::MAX);
+/// # }
+/// ```
+pub fn now_plus(d: Duration) -> Duration {
+  now().saturating_add(d)
+}
+
+/// Get the time the given duration after now. Returns ``None`` if the result would overflow.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(1_000);
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// assert_eq!(checked_now_plus(Duration::from_micros(10)), Some(Duration::from_micros(1_010)));
+/// let boundary = Duration::MAX - Duration::from_micros(1_000);
+/// assert_eq!(checked_now_plus(boundary), Some(Duration::MAX));
+/// assert_eq!(checked_now_plus(boundary + Duration::from_nanos(1)), None);
+/// # }
+/// ```
+pub fn checked_now_plus(d: Duration) -> Option<Duration> {
+  now().checked_add(d)
+}
+
+/// Get the resolution of the time provided by this crate, the smallest difference between two points in time that
+/// can be distinguished. This is the duration of one tick of the system timer, 1µs for the Raspberry Pi. Once the
+/// free running counter of the ARM timer has been configured with ``arm_timer::configure_free_running`` the finer
+/// resolution of its counter is returned as it is used by [now_ns] to interpolate within a tick.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// assert_eq!(precision(), Duration::from_micros(1));
+/// // with a pre-scaler of 0 the ARM timer counts with 250MHz
+/// arm_timer::configure_free_running(0);
+/// assert_eq!(precision(), Duration::from_nanos(4));
+/// # }
+/// ```
+pub fn precision() -> Duration {
+  let tick = ticks_to_duration(1);
+  match arm_timer::free_running_frequency() {
+    Some(freq) => {
+      // round up as the counter can not distinguish anything finer than one of its ticks
+      let freq = freq as u64;
+      let nanos = 1_000_000_000 / freq + (1_000_000_000 % freq != 0) as u64;
+      tick.min(Duration::from_nanos(nanos))
+    }
+    None => tick,
+  }
+}
+
+/// Get the current time in nano seconds since the system timer started counting. The micro seconds are taken from the
+/// 64Bit system timer, while the nano seconds within the current micro second are interpolated using the free running
+/// counter of the ARM timer. This requires [arm_timer::configure_free_running] to be called once before, otherwise
+/// the result only has the micro second resolution of [now].
+///
+/// The resolution is limited by the frequency of the free running counter, so configuring it with a pre-scaler of
+/// 0 gives the finest result. The free running counter is derived from the core clock, changing the core clock after
+/// the configuration leads to wrong interpolations, which are limited to the current micro second though.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// arm_timer::configure_free_running(0);
+/// let start = now_ns();
+/// sleepcycles(100);
+/// println!("took {} ns", now_ns() - start);
+/// # }
+/// ```
+pub fn now_ns() -> u64 {
+  loop {
+    let ticks = clock::ticks();
+    let within = arm_timer::nanos_within_micro(ticks, arm_timer::free_running_counter());
+    // ensure both counter values belong to the same micro second
+    if clock::ticks() == ticks {
+      let nanos = ticks_to_duration(ticks).as_nanos().min(u64::MAX as u128) as u64;
+      return nanos.saturating_add(within.unwrap_or(0));
+    }
+  }
+}
+
+/// Get the time passed since the system timer started counting. As the free running counter starts at 0 when
+/// the board is powered on this is the time since boot, unless a different zero point has been set with
+/// [reset_epoch].
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// let uptime = uptime();
+/// println!("running for {} ms", uptime.as_millis());
+/// # }
+/// ```
+pub fn uptime() -> Duration {
+  let epoch = ticks_to_duration(EPOCH.load(Ordering::Acquire));
+  now().checked_sub(epoch).unwrap_or_default()
+}
+
+/// Write the current [uptime] as ``HH:MM:SS.mmmuuu`` to the given writer, e.g. to prefix log messages with a human
+/// readable timestamp. The hours are not wrapped into days, so they grow beyond two digits after 100 hours.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// let mut formatted = String::new();
+/// format_uptime(&mut formatted).unwrap();
+/// assert_eq!(formatted, "00:00:00.000000");
+///
+/// // 1 hour, 2 minutes, 3 seconds and 4.005 milli seconds
+/// CLOCK.set(3_723_004_005);
+/// formatted.clear();
+/// format_uptime(&mut formatted).unwrap();
+/// assert_eq!(formatted, "01:02:03.004005");
+/// # }
+/// ```
+pub fn format_uptime<W: core::fmt::Write>(w: &mut W) -> core::fmt::Result {
+  let uptime = uptime();
+  let secs = uptime.as_secs();
+  write!(
+    w,
+    "{:02}:{:02}:{:02}.{:06}",
+    secs / 3_600,
+    secs / 60 % 60,
+    secs % 60,
+    uptime.subsec_micros()
+  )
+}
+
+/// Set the current time as the new zero point for [uptime]. This is useful for logging timestamps that shall
+/// start at program entry rather than at board power-on.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// reset_epoch();
+/// // uptime is now relative to the call to reset_epoch
+/// let since_start = uptime();
+/// # }
+/// ```
+pub fn reset_epoch() {
+  EPOCH.store(duration_to_ticks(now()), Ordering::Release);
+}
+
+/// Get the next point in time, as free running counter value like returned by [now], that is a whole multiple of the
+/// given period since the zero point of [uptime]. This allows to phase align a periodic loop to a boundary, e.g. a
+/// 1kHz control loop to the milliseconds since boot. If the current time is exactly on a boundary it is returned
+/// as is. A period of zero has no boundaries and returns the current time.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(1_234);
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// assert_eq!(align_to(Duration::from_millis(1)), Duration::from_micros(2_000));
+/// assert_eq!(align_to(Duration::from_micros(617)), Duration::from_micros(1_234));
+/// assert_eq!(align_to(Duration::ZERO), Duration::from_micros(1_234));
+/// // start the loop on the next millisecond boundary
+/// sleep_until(align_to(Duration::from_millis(1)));
+/// # }
+/// ```
+pub fn align_to(period: Duration) -> Duration {
+  let current = now();
+  if period.is_zero() {
+    return current;
+  }
+  let epoch = ticks_to_duration(EPOCH.load(Ordering::Acquire));
+  let since_epoch = current.checked_sub(epoch).unwrap_or_default().as_nanos();
+  let period_nanos = period.as_nanos();
+  let boundary = since_epoch + (period_nanos - since_epoch % period_nanos) % period_nanos;
+  let secs = boundary / 1_000_000_000;
+  if secs > u64::MAX as u128 {
+    return Duration::MAX;
+  }
+
+  epoch.saturating_add(Duration::new(
+    secs as u64,
+    (boundary % 1_000_000_000) as u32,
+  ))
+}
+
+/// Convert the given duration into the amount of ticks of the free running counter of the system timer running at
+/// [timer_frequency]. Durations exceeding the range of the 64Bit counter saturate at ``u64::MAX``.
+///
+/// All conversions between ticks and time use 128Bit intermediate values, so even high frequencies of the counter
+/// and long durations do not overflow while converting.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// assert_eq!(duration_to_ticks(Duration::from_millis(1)), 1_000);
+/// # }
+/// ```
+///
+/// With a counter running at 4GHz the nano seconds of a day multiplied with the frequency exceed the range of 64Bit
+/// by far, while the resulting ticks still fit:
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// configure(TimerConfig {
+///   frequency_hz: 4_000_000_000,
+///   ..TimerConfig::DEFAULT
+/// })
+/// .unwrap();
+/// let day = Duration::from_secs(86_400);
+/// assert_eq!(duration_to_ticks(day), 345_600_000_000_000);
+/// assert_eq!(ticks_to_duration(345_600_000_000_000), day);
+/// assert_eq!(ticks_to_duration(u64::MAX), Duration::new(4_611_686_018, 427_387_903));
+/// assert_eq!(duration_to_ticks(Duration::MAX), u64::MAX);
+/// # }
+/// ```
+pub fn duration_to_ticks(d: Duration) -> u64 {
+  // the product exceeds even 128Bit only for durations far beyond the range of the counter
+  let ticks = d
+    .as_nanos()
+    .checked_mul(timer_frequency() as u128)
+    .map_or(u128::MAX, |product| product / 1_000_000_000);
+  if ticks > u64::MAX as u128 {
+    u64::MAX
+  } else {
+    ticks as u64
+  }
+}
+
+/// Convert the given amount of ticks of the free running counter of the system timer running at [timer_frequency]
+/// into a duration.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// assert_eq!(ticks_to_duration(1_000), Duration::from_millis(1));
+/// # }
+/// ```
+pub fn ticks_to_duration(t: u64) -> Duration {
+  let hz = timer_frequency();
+  let secs = t / hz;
+  let nanos = (t % hz) as u128 * 1_000_000_000 / hz as u128;
+  Duration::new(secs, nanos as u32)
+}
+
+/// Get the time that has passed since the given earlier point in time, as free running counter value like returned
+/// by [now]. If the given time lies in the future, zero is returned instead of panicking.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// let start = now();
+/// sleep(Duration::from_millis(1));
+/// assert!(elapsed_since(start) >= Duration::from_millis(1));
+/// // a time in the future has not elapsed at all
+/// assert_eq!(elapsed_since(now() + Duration::from_secs(1)), Duration::ZERO);
+/// # }
+/// ```
+pub fn elapsed_since(earlier: Duration) -> Duration {
+  now().checked_sub(earlier).unwrap_or_default()
+}
+
+/// Get the ticks of the free running counter that have passed since the given [Instant]. The counter of the active
+/// [TimeSource] is read and subtracted as plain tick value without converting it into a [Duration], so this is the
+/// cheapest way to measure short intervals, e.g. for profiling counters in a hot path. If the given [Instant] lies
+/// in the future, zero is returned.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// let start = Instant::now();
+/// CLOCK.advance(1_234);
+/// assert_eq!(ticks_since(start), 1_234);
+/// assert_eq!(ticks_since(start), duration_to_ticks(start.elapsed()));
+/// // an instant in the future has not passed at all
+/// assert_eq!(ticks_since(Instant::now() + Duration::from_secs(1)), 0);
+/// # }
+/// ```
+pub fn ticks_since(earlier: Instant) -> u64 {
+  clock::ticks().saturating_sub(earlier.as_ticks())
+}
+
+/// Run the given function and measure the time it took to execute. Returns the result of the function together
+/// with the elapsed time.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// let (sum, elapsed) = measure(|| (0..1_000u32).sum::<u32>());
+/// println!("calculated {} in {} µs", sum, elapsed.as_micros());
+/// # }
+/// ```
+pub fn measure<R, F: FnOnce() -> R>(f: F) -> (R, Duration) {
+  let start = now();
+  let result = f();
+  let elapsed = elapsed_since(start);
+
+  (result, elapsed)
+}
+
+/// The counter the cycles of a [Measurement] have been taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CycleSource {
+  /// The cycle counter of the performance monitors unit, counting CPU cycles
+  Pmu,
+  /// The free running counter of the ARM timer, counting with the frequency set with
+  /// ``arm_timer::configure_free_running``
+  ArmTimer,
+  /// No counter, the cycles are derived from the measured time at the CPU frequency set with
+  /// [cycles::set_cpu_frequency]. This is used outside of bare metal where neither counter is accessible.
+  TimeSource,
+}
+
+/// The result of a function together with the time and cycles it took to execute, see [measure_detailed]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Measurement<R> {
+  result: R,
+  duration: Duration,
+  cycles: u64,
+  source: CycleSource,
+}
+
+impl<R> Measurement<R> {
+  /// The time the function took as measured with the system timer
+  pub fn duration(&self) -> Duration {
+    self.duration
+  }
+
+  /// The cycles the function took, counted by the [Measurement::cycle_source]
+  pub fn cycles(&self) -> u64 {
+    self.cycles
+  }
+
+  /// The counter the cycles have been taken from
+  pub fn cycle_source(&self) -> CycleSource {
+    self.source
+  }
 
-  Duration::from_micros((t_high << 32) | t_low)
+  /// The result of the function
+  pub fn result(&self) -> &R {
+    &self.result
+  }
+
+  /// Take the result of the function out of the measurement
+  pub fn into_result(self) -> R {
+    self.result
+  }
+}
+
+/// Run the given function and measure the time as well as the cycles it took to execute, e.g. for micro
+/// benchmarks. The cycles are taken from the cycle counter of the performance monitors unit if it has been enabled,
+/// see [cycles::cycle_counter]. Otherwise they are taken from the free running counter of the ARM timer, which need
+/// to be enabled with ``arm_timer::configure_free_running`` and counts with the frequency set there instead of the
+/// CPU frequency. The ARM timer counter is only 32Bit wide, so it wraps around for long measurements. Outside of bare
+/// metal, e.g. on an aarch64 Linux host, the cycles are derived from the time measured with the time source.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// arm_timer::configure_free_running(0);
+/// let measurement = measure_detailed(|| {
+///   sleepcycles(10_000);
+///   42
+/// });
+/// assert!(measurement.duration() > Duration::ZERO);
+/// assert_eq!(*measurement.result(), 42);
+/// println!("took {} cycles of {:?}", measurement.cycles(), measurement.cycle_source());
+/// # }
+/// ```
+pub fn measure_detailed<R, F: FnOnce() -> R>(f: F) -> Measurement<R> {
+  match cycles::cycle_counter() {
+    Some(start_cycles) => {
+      let start = now();
+      let result = f();
+      let duration = elapsed_since(start);
+      let cycles = cycles::cycle_counter()
+        .unwrap_or(start_cycles)
+        .wrapping_sub(start_cycles);
+      Measurement {
+        result,
+        duration,
+        cycles,
+        source: CycleSource::Pmu,
+      }
+    }
+    #[cfg(all(target_arch = "aarch64", target_os = "none"))]
+    None => {
+      let start_cycles = arm_timer::free_running_counter();
+      let start = now();
+      let result = f();
+      let duration = elapsed_since(start);
+      let cycles = arm_timer::free_running_counter().wrapping_sub(start_cycles);
+      Measurement {
+        result,
+        duration,
+        cycles: cycles as u64,
+        source: CycleSource::ArmTimer,
+      }
+    }
+    // the ARM timer register are only accessible on bare metal
+    #[cfg(not(all(target_arch = "aarch64", target_os = "none")))]
+    None => {
+      let (result, duration) = measure(f);
+      Measurement {
+        result,
+        duration,
+        cycles: cycles::from_duration(duration),
+        source: CycleSource::TimeSource,
+      }
+    }
+  }
+}
+
+/// Cooperative deadline for synchronous code. As a running closure can not be interrupted, long running loops
+/// shall poll [DeadlineChecker::is_expired] regularly and bail out once the budget has been used up.
+#[derive(Debug, Clone, Copy)]
+pub struct DeadlineChecker {
+  deadline: Duration,
+}
+
+impl DeadlineChecker {
+  /// Returns true if the time budget of this checker has been used up
+  pub fn is_expired(&self) -> bool {
+    now() >= self.deadline
+  }
+
+  /// Returns the time left until the budget is used up, or zero if it has already expired
+  pub fn remaining(&self) -> Duration {
+    self.deadline.checked_sub(now()).unwrap_or_default()
+  }
+}
+
+/// Create a [DeadlineChecker] that expires once the given time budget has passed from now on.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// let checker = deadline_checker(Duration::from_millis(5));
+/// let mut iterations = 0u32;
+/// loop {
+///   if checker.is_expired() {
+///     println!("gave up after {} iterations", iterations);
+///     break;
+///   }
+///   // do some chunk of work
+///   iterations += 1;
+/// }
+/// # }
+/// ```
+pub fn deadline_checker(budget: Duration) -> DeadlineChecker {
+  DeadlineChecker {
+    deadline: now_plus(budget),
+  }
 }
 
 /// Compare the given time as free running counter value with the current time.
-/// Returns true if the current time is later than the time passed into this function.
+/// Returns true if the current time has reached or passed the time passed into this function. This is a helper
+/// for custom timing loops based on [now]. As the free running counter never goes below zero a deadline of
+/// ``Duration::ZERO`` is always due, there is no special "unset" value.
 /// # Example
 /// ```no_run
 /// # use ruspiro_timer::*;
 /// # fn doc() {
 /// let due_time = now() + Duration::from_secs(100);
 /// if is_due(due_time) {
-///     println!("Time is due :)");    
+///     println!("Time is due :)");
 /// }
+/// // a deadline of zero is always due
+/// assert!(is_due(Duration::ZERO));
+/// // a deadline in the future is not due yet
+/// assert!(!is_due(now() + Duration::from_secs(1)));
 /// # }
 /// ```
-fn is_due(time: Duration) -> bool {
-  if time == Duration::from_micros(0) {
-    // if no valid time is given, time is always due
-    true
-  } else {
-    // returns true if we have reached the current time (counter)
-    now() >= time
+pub fn is_due(time: Duration) -> bool {
+  // returns true if we have reached the current time (counter)
+  now() >= time
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{setup, CLOCK, TICKING};
+
+  #[test]
+  fn measure_returns_result_and_elapsed_time() {
+    let _guard = setup();
+    CLOCK.set(1_000);
+
+    let (result, elapsed) = measure(|| {
+      CLOCK.advance(250);
+      42
+    });
+    assert_eq!(result, 42);
+    assert_eq!(elapsed, Duration::from_micros(250));
+  }
+
+  #[test]
+  fn measure_without_time_passing_is_zero() {
+    let _guard = setup();
+
+    let ((), elapsed) = measure(|| ());
+    assert_eq!(elapsed, Duration::ZERO);
+  }
+
+  #[test]
+  fn sleepcycles_at_least_waits_for_the_time_the_cycles_take() {
+    let _guard = setup();
+    set_time_source(&TICKING);
+    // without the cycle counter the time the cycles take at the CPU frequency is waited for
+    assert_eq!(cycles::cycle_counter(), None);
+    let cycles = cycles::from_duration(Duration::from_micros(100));
+
+    let start = now();
+    sleepcycles_at_least(cycles);
+    assert!(elapsed_since(start) >= cycles::to_duration(cycles));
+  }
+
+  #[test]
+  fn measure_detailed_derives_cycles_from_time_source_on_host() {
+    let _guard = setup();
+
+    let measurement = measure_detailed(|| CLOCK.advance(10));
+    assert_eq!(measurement.duration(), Duration::from_micros(10));
+    assert_eq!(measurement.cycle_source(), CycleSource::TimeSource);
+    assert_eq!(
+      measurement.cycles(),
+      cycles::from_duration(Duration::from_micros(10))
+    );
+  }
+
+  #[test]
+  fn self_test_passes_with_advancing_clock() {
+    let _guard = setup();
+    set_time_source(&TICKING);
+
+    assert_eq!(self_test(), Ok(()));
+  }
+
+  #[test]
+  fn self_test_fails_with_frozen_clock() {
+    let _guard = setup();
+    CLOCK.set(1_000);
+
+    assert_eq!(self_test(), Err(TimerError::NotAdvancing));
+  }
+
+  #[test]
+  fn run_until_deadline_times_out_at_deadline() {
+    let _guard = setup();
+    set_time_source(&TICKING);
+    let deadline = now_plus(Duration::from_micros(100));
+
+    let mut steps = 0;
+    let result: Result<(), _> = run_until_deadline(deadline, || {
+      steps += 1;
+      Poll::Pending
+    });
+    assert_eq!(result, Err(TimedOut));
+    // the deadline is checked with one read of the clock after each step, so it times out with the 100th tick
+    assert_eq!(steps, 100);
+  }
+
+  #[test]
+  fn sleep_with_waits_for_duration() {
+    let _guard = setup();
+    set_time_source(&TICKING);
+
+    // the low power strategy only spins as well without the interrupts, otherwise it needs the actual system timer
+    #[cfg(not(feature = "ruspiro-interrupt"))]
+    let strategies = [
+      SpinStrategy::Tight,
+      SpinStrategy::Hint,
+      SpinStrategy::LowPower,
+    ];
+    #[cfg(feature = "ruspiro-interrupt")]
+    let strategies = [SpinStrategy::Tight, SpinStrategy::Hint];
+    for strategy in strategies {
+      let start = now();
+      sleep_with(Duration::from_micros(100), strategy);
+      assert!(
+        elapsed_since(start) >= Duration::from_micros(100),
+        "{:?}",
+        strategy
+      );
+    }
+  }
+
+  /// The [uptime] formatted by [format_uptime]
+  fn formatted_uptime() -> std::string::String {
+    let mut formatted = std::string::String::new();
+    format_uptime(&mut formatted).unwrap();
+    formatted
+  }
+
+  #[test]
+  fn format_uptime_writes_hours_minutes_seconds_and_micros() {
+    let _guard = setup();
+    assert_eq!(formatted_uptime(), "00:00:00.000000");
+
+    for (ticks, expected) in [
+      (999_999, "00:00:00.999999"),
+      (3_723_004_005, "01:02:03.004005"),
+      (86_399_999_999, "23:59:59.999999"),
+      // the hours are not wrapped into days
+      (360_000_000_000, "100:00:00.000000"),
+    ] {
+      CLOCK.set(ticks);
+      assert_eq!(formatted_uptime(), expected);
+    }
+  }
+
+  #[test]
+  fn format_uptime_starts_at_the_epoch() {
+    let _guard = setup();
+    CLOCK.set(5_000_000);
+    reset_epoch();
+
+    CLOCK.advance(61_000_250);
+    assert_eq!(formatted_uptime(), "00:01:01.000250");
+  }
+
+  #[test]
+  fn now_plus_saturates_near_counter_limit() {
+    let _guard = setup();
+    CLOCK.set(u64::MAX - 1);
+
+    assert_eq!(
+      now_plus(Duration::from_micros(1)),
+      Duration::from_micros(u64::MAX)
+    );
+    assert_eq!(now_plus(Duration::MAX), Duration::MAX);
+    // the saturated deadline is never due, so sleeping for it waits forever instead of panicking
+    assert!(!is_due(now_plus(Duration::MAX)));
+  }
+
+  #[test]
+  fn wait_for_returns_elapsed_time_once_predicate_holds() {
+    let _guard = setup();
+    assert_eq!(wait_for(|| true, Duration::ZERO), Ok(Duration::ZERO));
+
+    let mut polls = 0;
+    let elapsed = wait_for(
+      || {
+        CLOCK.advance(10);
+        polls += 1;
+        polls == 3
+      },
+      Duration::from_micros(100),
+    );
+    assert_eq!(elapsed, Ok(Duration::from_micros(30)));
+  }
+
+  #[test]
+  fn wait_for_times_out_at_deadline() {
+    let _guard = setup();
+
+    let mut polls = 0;
+    let elapsed = wait_for(
+      || {
+        CLOCK.advance(10);
+        polls += 1;
+        false
+      },
+      Duration::from_micros(100),
+    );
+    assert_eq!(elapsed, Err(TimedOut));
+    assert_eq!(polls, 10);
+    assert_eq!(now(), Duration::from_micros(100));
+  }
+
+  #[test]
+  fn sleep_aliases_wait_for_their_unit() {
+    let _guard = setup();
+    set_time_source(&TICKING);
+
+    let start = now();
+    sleep_us(150);
+    assert!(elapsed_since(start) >= Duration::from_micros(150));
+
+    let start = now();
+    sleep_ms(2);
+    assert!(elapsed_since(start) >= Duration::from_millis(2));
+
+    // a second is a million reads of the ticking clock, which still passes quickly
+    let start = now();
+    sleep_secs(1);
+    assert!(elapsed_since(start) >= Duration::from_secs(1));
+  }
+
+  #[test]
+  fn checked_now_plus_fails_where_now_plus_saturates() {
+    let _guard = setup();
+    CLOCK.set(1_000);
+
+    assert_eq!(
+      now_plus(Duration::from_micros(10)),
+      Duration::from_micros(1_010)
+    );
+    assert_eq!(
+      checked_now_plus(Duration::from_micros(10)),
+      Some(Duration::from_micros(1_010))
+    );
+
+    let boundary = Duration::MAX - Duration::from_micros(1_000);
+    assert_eq!(now_plus(boundary), Duration::MAX);
+    assert_eq!(checked_now_plus(boundary), Some(Duration::MAX));
+
+    let beyond = boundary + Duration::from_nanos(1);
+    assert_eq!(now_plus(beyond), Duration::MAX);
+    assert_eq!(checked_now_plus(beyond), None);
+  }
+
+  #[test]
+  fn align_to_returns_next_period_boundary() {
+    let _guard = setup();
+    CLOCK.set(1_234);
+
+    assert_eq!(
+      align_to(Duration::from_millis(1)),
+      Duration::from_micros(2_000)
+    );
+    assert_eq!(
+      align_to(Duration::from_micros(617)),
+      Duration::from_micros(1_234)
+    );
+    assert_eq!(align_to(Duration::ZERO), Duration::from_micros(1_234));
+
+    // a boundary that is due already is returned as is
+    CLOCK.set(2_000);
+    assert_eq!(
+      align_to(Duration::from_millis(1)),
+      Duration::from_micros(2_000)
+    );
+  }
+
+  #[test]
+  fn align_to_is_relative_to_the_epoch() {
+    let _guard = setup();
+    CLOCK.set(300);
+    reset_epoch();
+
+    CLOCK.set(1_234);
+    assert_eq!(
+      align_to(Duration::from_millis(1)),
+      Duration::from_micros(1_300)
+    );
+  }
+
+  #[test]
+  fn sleep_cancellable_returns_once_cancelled_from_another_thread() {
+    let _guard = setup();
+    let cancel = std::sync::Arc::new(AtomicBool::new(false));
+
+    // the clock does not reach the deadline on its own, so only the cancellation ends the sleep
+    let canceller = {
+      let cancel = std::sync::Arc::clone(&cancel);
+      std::thread::spawn(move || {
+        CLOCK.advance(500);
+        cancel.store(true, Ordering::Release);
+      })
+    };
+    assert_eq!(
+      sleep_cancellable(Duration::from_millis(1), &cancel),
+      SleepOutcome::Cancelled
+    );
+    canceller.join().unwrap();
+    assert!(now() < Duration::from_millis(1));
+  }
+
+  #[test]
+  fn sleep_cancellable_completes_without_cancellation() {
+    let _guard = setup();
+    set_time_source(&TICKING);
+    let cancel = AtomicBool::new(false);
+
+    let start = now();
+    assert_eq!(
+      sleep_cancellable(Duration::from_micros(100), &cancel),
+      SleepOutcome::Completed
+    );
+    assert!(elapsed_since(start) >= Duration::from_micros(100));
+
+    // a flag set already cancels before the duration has passed
+    cancel.store(true, Ordering::Release);
+    assert_eq!(
+      sleep_cancellable(Duration::from_micros(100), &cancel),
+      SleepOutcome::Cancelled
+    );
+  }
+
+  #[test]
+  fn ticks_since_agrees_with_elapsed() {
+    let _guard = setup();
+    CLOCK.set(1_000);
+    let start = Instant::now();
+
+    CLOCK.advance(1_234);
+    assert_eq!(ticks_since(start), 1_234);
+    assert_eq!(ticks_since(start), duration_to_ticks(start.elapsed()));
+    // an instant in the future has not passed at all
+    assert_eq!(ticks_since(Instant::now() + Duration::from_secs(1)), 0);
   }
 }
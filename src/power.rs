@@ -0,0 +1,94 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Low Power Pause
+//!
+//! Pause the current execution without spinning the core at full power. The core is halted with
+//! ``wfe`` until the system timer compare channel 3 raises an interrupt at the requested deadline.
+//! If the feature ``schedule-timer3`` is active this channel is owned by the scheduler and the
+//! pause falls back to a busy wait. The same applies without the optional ``ruspiro-interrupt``
+//! dependency, as the interrupt can not be used then.
+//!
+
+#[cfg(all(feature = "ruspiro-interrupt", not(feature = "schedule-timer3")))]
+use crate::compare::{self, Channel};
+#[cfg(all(feature = "ruspiro-interrupt", not(feature = "schedule-timer3")))]
+use crate::interface::*;
+use crate::*;
+#[cfg(all(feature = "ruspiro-interrupt", not(feature = "schedule-timer3")))]
+use ruspiro_arch_aarch64::instructions::wfe;
+#[cfg(all(feature = "ruspiro-interrupt", not(feature = "schedule-timer3")))]
+use ruspiro_interrupt::{self as irq, Interrupt, IrqHandler, IsrSender};
+
+/// Pause the current execution for the given amount of time and halt the core while waiting. The system timer
+/// compare channel 3 is programmed to raise an interrupt once the time is due which wakes up the core.
+///
+/// ## Hint:
+/// The interrupts need to be initialized and globally enabled using the
+/// [``ruspiro_interrupt`` crate](https://crates.io/crates/ruspiro_interrupt) for the core to be woken up by the
+/// timer interrupt. If the compare channel is already in use by a different core or has been claimed with
+/// [compare::claim] the current execution falls back to a normal busy wait like [sleep]. This is also the case if the compare channel is used by the scheduler with the
+/// feature ``schedule-timer3`` active.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// // halt the core for 1 second
+/// sleep_low_power(Duration::from_secs(1));
+/// # }
+/// ```
+#[cfg(all(feature = "ruspiro-interrupt", not(feature = "schedule-timer3")))]
+pub fn sleep_low_power(duration: Duration) {
+  let wait_until = now_plus(duration);
+
+  let channel = match compare::claim(Channel::C3) {
+    Ok(channel) => channel,
+    Err(_) => {
+      // the compare channel is not available, fall back to spinning
+      while !is_due(wait_until) {
+        core::hint::spin_loop();
+      }
+      return;
+    }
+  };
+
+  // clear any stale match first as the interrupt would be immediately triggered otherwise
+  channel.clear();
+  channel.arm(wait_until);
+  irq::activate(Interrupt::SystemTimer3, None);
+
+  // the event register is set on return from the interrupt, so even if the timer interrupt is raised between the
+  // check and the wfe the core will not sleep forever
+  while !is_due(wait_until) {
+    wfe();
+  }
+
+  irq::deactivate(Interrupt::SystemTimer3);
+  // the channel is released only after its interrupt is deactivated
+  drop(channel);
+}
+
+/// Pause the current execution for the given amount of time. As the compare channel 3 is used by the scheduler
+/// with the feature ``schedule-timer3``, or the interrupts are not available without the ``ruspiro-interrupt``
+/// dependency, this is a normal busy wait like [sleep].
+#[cfg(any(not(feature = "ruspiro-interrupt"), feature = "schedule-timer3"))]
+pub fn sleep_low_power(duration: Duration) {
+  sleep(duration);
+}
+
+/// Implement the timer interrupt handler that wakes up the core waiting in [sleep_low_power]
+#[cfg(all(feature = "ruspiro-interrupt", not(feature = "schedule-timer3")))]
+#[IrqHandler(SystemTimer3)]
+unsafe fn wakeup_handler(channel: Option<IsrSender<Box<dyn Any>>>) {
+  // only care on the match flag of channel 3, the other flags belong to different owners
+  let status = SYS_TIMERCS().get();
+  if status & SYS_TIMERCS::M3::MATCH.raw_value() != 0 {
+    // only acknowledge the match, returning from the interrupt wakes up the halted core
+    SYS_TIMERCS().write_value(SYS_TIMERCS::M3::MATCH);
+  }
+}
@@ -0,0 +1,133 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Rate Limiter
+//!
+//! Throttle events like log output or the polling of a sensor to happen at most once per interval.
+//!
+
+use crate::*;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Marker for a rate limiter that has not allowed any event yet
+const NEVER: u64 = u64::MAX;
+
+/// Limits events to happen at most once per minimum interval. The time of the last allowed event is kept atomically,
+/// so the same rate limiter can be shared between cores and used from an interrupt handler.
+#[derive(Debug)]
+pub struct RateLimiter {
+  min_interval: Duration,
+  /// The counter value of the system timer the last event has been allowed at
+  last: AtomicU64,
+}
+
+impl RateLimiter {
+  /// Create a rate limiter allowing an event at most once per ``min_interval``. The first event is always allowed.
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_timer::*;
+  /// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+  /// static LOG_LIMIT: RateLimiter = RateLimiter::new(Duration::from_millis(100));
+  ///
+  /// # fn doc() {
+  /// set_time_source(&CLOCK);
+  /// assert!(LOG_LIMIT.allow());
+  /// // further events within the interval are rejected
+  /// CLOCK.advance(99_999);
+  /// assert!(!LOG_LIMIT.allow());
+  /// // once the interval has passed since the last allowed event the next one is allowed
+  /// CLOCK.advance(1);
+  /// assert!(LOG_LIMIT.allow());
+  /// assert!(!LOG_LIMIT.allow());
+  /// # }
+  /// ```
+  pub const fn new(min_interval: Duration) -> Self {
+    Self {
+      min_interval,
+      last: AtomicU64::new(NEVER),
+    }
+  }
+
+  /// The minimum interval between two allowed events
+  pub fn min_interval(&self) -> Duration {
+    self.min_interval
+  }
+
+  /// Check whether an event may happen now. Returns ``true`` if no event has been allowed within the minimum
+  /// interval and records this as the last allowed event. If several cores ask at the same time only one of them is
+  /// allowed.
+  pub fn allow(&self) -> bool {
+    let current = duration_to_ticks(now());
+    let mut last = self.last.load(Ordering::Acquire);
+    loop {
+      if last != NEVER && ticks_to_duration(current.saturating_sub(last)) < self.min_interval {
+        return false;
+      }
+      match self
+        .last
+        .compare_exchange_weak(last, current, Ordering::AcqRel, Ordering::Acquire)
+      {
+        Ok(_) => return true,
+        Err(actual) => last = actual,
+      }
+    }
+  }
+
+  /// Forget the last allowed event, so the next event is allowed regardless of the interval
+  pub fn reset(&self) {
+    self.last.store(NEVER, Ordering::Release);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{setup, CLOCK};
+
+  #[test]
+  fn first_event_is_allowed() {
+    let _guard = setup();
+    let limiter = RateLimiter::new(Duration::from_millis(100));
+
+    assert!(limiter.allow());
+    assert!(!limiter.allow());
+  }
+
+  #[test]
+  fn event_within_interval_is_rejected() {
+    let _guard = setup();
+    let limiter = RateLimiter::new(Duration::from_millis(100));
+    assert!(limiter.allow());
+
+    CLOCK.advance(99_999);
+    assert!(!limiter.allow());
+  }
+
+  #[test]
+  fn event_at_min_interval_is_allowed() {
+    let _guard = setup();
+    let limiter = RateLimiter::new(Duration::from_millis(100));
+    assert!(limiter.allow());
+
+    CLOCK.advance(100_000);
+    assert!(limiter.allow());
+    // the interval starts over with the event allowed last
+    CLOCK.advance(99_999);
+    assert!(!limiter.allow());
+  }
+
+  #[test]
+  fn reset_allows_next_event() {
+    let _guard = setup();
+    let limiter = RateLimiter::new(Duration::from_millis(100));
+    assert!(limiter.allow());
+
+    limiter.reset();
+    assert!(limiter.allow());
+    assert!(!limiter.allow());
+  }
+}
@@ -7,84 +7,666 @@
 
 //! # Schedule Functions
 //!
-//! Allowing functions/closures to be scheduled with a specific delay. The scheduling is based on
-//! timer interrupts. When using this functionality ensure the interrupts are properly initialized
-//! and globally activated using the [``ruspiro_interrupt`` crate](https://crates.io/crates/ruspiro_interrupt)
+//! Allowing functions/closures to be scheduled with a specific delay. With the feature ``irq-schedule``
+//! active the scheduling is based on timer interrupts. When using this functionality ensure the
+//! interrupts are properly initialized and globally activated using the
+//! [``ruspiro_interrupt`` crate](https://crates.io/crates/ruspiro_interrupt).
+//!
+//! Without interrupts the scheduled functions are executed by calling [poll_schedules] regularly, e.g. from the
+//! main loop of the program.
+//!
+//! The scheduler uses the system timer compare channel 1 and the ``SystemTimer1`` interrupt. With the feature
+//! ``schedule-timer3`` active the compare channel 3 and the ``SystemTimer3`` interrupt is used instead. Those are
+//! the only channels safe to be used as channel 0 and 2 are used by the GPU. The scheduler expects to be the only
+//! owner of its channel, so it must not be used concurrently with other code arming or acknowledging the same
+//! channel. All accesses to the channel go through a [RegisterAccess], which can be replaced to test the
+//! scheduling without the hardware.
+//!
+//! The scheduled functions are kept in a list sorted by their due time. With the feature ``timing-wheel`` active a
+//! hashed timing wheel is used instead, which keeps inserting and expiring cheap even with thousands of pending
+//! functions. Both are only accessed with mutual exclusive access to the schedules. The earliest due function is
+//! taken out of them together with arming the timer for the next one, the function itself is executed after this
+//! access has been released. As functions are always taken by their due time, a function inserted before the ones
+//! already queued can never be skipped or executed twice.
+//!
+//! The timer interrupt handler needs the mutual exclusive access as well. The lock of the schedules spins until it
+//! is released but does not mask any interrupt on its own. So outside of the interrupt handler it is only ever taken
+//! with the IRQ of the current core masked. This keeps the timer interrupt from being raised on a core that holds
+//! the lock, which would wait for itself forever. While the lock is held by another core the handler only waits
+//! for its short critical section to end.
+//!
+//! Scheduling a new function from within a scheduled function is supported. Such a function is put into a lock free
+//! intake queue, so scheduling from within the timer interrupt never waits for the lock of the schedules. The queue
+//! is drained into the schedules with the next masked access to them, at the latest once the scheduled function has
+//! returned. Functions scheduled from the thread context are inserted into the schedules directly, with the IRQ of
+//! the current core masked as described above. Other operations like [cancel] or [shutdown_scheduler] must not be
+//! called from within a scheduled function.
+//!
+//! A scheduled function may run long enough for the next compare match to raise the timer interrupt again while it
+//! is still executed. If nested interrupts are enabled, this nested interrupt only acknowledges the match and leaves
+//! the due functions to the interrupt already executing them on the same core, which re-evaluates the schedules
+//! once the current function has returned. So the execution of the scheduled functions is never re-entered on a
+//! core and each function is executed exactly once, in the order of their due time. The same applies to
+//! [poll_schedules] called from within a scheduled function.
+//!
+//! # Example
+//! ```no_run
+//! # use ruspiro_timer::*;
+//! # fn doc() {
+//! schedule(Duration::from_millis(10), || {
+//!   println!("first");
+//!   // schedule a follow up function from within the scheduled one
+//!   schedule(Duration::from_millis(10), || println!("second"));
+//! });
+//! sleep(Duration::from_millis(30));
+//! # }
+//! ```
 //!
 
 extern crate alloc;
+use crate::critical::irq_masked;
+use crate::inline_function::InlineFunction;
+#[cfg(feature = "timing-wheel")]
+use crate::timing_wheel::TimingWheel;
 use crate::*;
-use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+#[cfg(not(feature = "timing-wheel"))]
+use alloc::collections::BTreeMap;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use core::{
-  cell::UnsafeCell,
-  sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+  ops::ControlFlow,
+  ptr,
+  sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering},
   time::Duration,
 };
-use ruspiro_interrupt::{self as irq, Interrupt, IrqHandler, IsrSender};
+use ruspiro_channel::mpmc::{channel, Receiver};
+#[cfg(feature = "irq-schedule")]
+use ruspiro_interrupt::{self as irq, IrqHandler, IsrSender};
 use ruspiro_singleton::*;
 
-type FunctionScheduleList =
-  BTreeMap<Duration, UnsafeCell<Option<Box<dyn FnOnce() + 'static + Send>>>>;
+/// Access to the system timer compare channel 1 used by the scheduler
+#[cfg(not(feature = "schedule-timer3"))]
+mod channel {
+  use crate::interface::*;
+
+  /// The interrupt raised if the compare value of this channel matches the free running counter
+  #[cfg(feature = "irq-schedule")]
+  pub const INTERRUPT: ruspiro_interrupt::Interrupt = ruspiro_interrupt::Interrupt::SystemTimer1;
+
+  /// Set the compare value the interrupt shall be raised at. The compare register only matches the lower 32Bit
+  /// of the free running counter
+  pub fn set_compare(value: u32) {
+    SYS_TIMERC1().set(value);
+  }
+
+  /// Check whether the control/status register indicates a match of this channel
+  pub fn is_matched() -> bool {
+    SYS_TIMERCS().get() & SYS_TIMERCS::M1::MATCH.raw_value() != 0
+  }
+
+  /// Clear the match flag of this channel by writing 1 to it
+  pub fn clear_match() {
+    SYS_TIMERCS().write_value(SYS_TIMERCS::M1::MATCH);
+  }
+}
+
+/// Access to the system timer compare channel 3 used by the scheduler
+#[cfg(feature = "schedule-timer3")]
+mod channel {
+  use crate::interface::*;
+
+  /// The interrupt raised if the compare value of this channel matches the free running counter
+  #[cfg(feature = "irq-schedule")]
+  pub const INTERRUPT: ruspiro_interrupt::Interrupt = ruspiro_interrupt::Interrupt::SystemTimer3;
+
+  /// Set the compare value the interrupt shall be raised at. The compare register only matches the lower 32Bit
+  /// of the free running counter
+  pub fn set_compare(value: u32) {
+    SYS_TIMERC3().set(value);
+  }
+
+  /// Check whether the control/status register indicates a match of this channel
+  pub fn is_matched() -> bool {
+    SYS_TIMERCS().get() & SYS_TIMERCS::M3::MATCH.raw_value() != 0
+  }
+
+  /// Clear the match flag of this channel by writing 1 to it
+  pub fn clear_match() {
+    SYS_TIMERCS().write_value(SYS_TIMERCS::M3::MATCH);
+  }
+}
+
+/// Access to the compare channel register used by the scheduler. The scheduler only programs its channel through
+/// the active implementation set with [set_register_access], which defaults to the real system timer register. A
+/// test can replace it with a spy that records the programmed values to verify the scheduling logic without the
+/// hardware.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicU32, Ordering};
+/// struct Spy {
+///   compare: AtomicU32,
+/// }
+///
+/// impl RegisterAccess for Spy {
+///   fn set_compare(&self, value: u32) {
+///     self.compare.store(value, Ordering::Release);
+///   }
+///   fn clear_match(&self) {}
+///   fn is_matched(&self) -> bool {
+///     false
+///   }
+/// }
+///
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// static SPY: Spy = Spy { compare: AtomicU32::new(0) };
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// set_register_access(&SPY);
+/// schedule(Duration::from_millis(20), || ());
+/// assert_eq!(SPY.compare.load(Ordering::Acquire), 20_000);
+/// // an earlier function scheduled later on re-arms the compare value
+/// schedule(Duration::from_millis(10), || ());
+/// assert_eq!(SPY.compare.load(Ordering::Acquire), 10_000);
+/// // a later function keeps the compare value
+/// schedule(Duration::from_millis(30), || ());
+/// assert_eq!(SPY.compare.load(Ordering::Acquire), 10_000);
+/// # }
+/// ```
+pub trait RegisterAccess: Sync {
+  /// Set the compare value the scheduler channel shall match at. Only the lower 32Bit of the free running counter
+  /// are compared.
+  fn set_compare(&self, value: u32);
+  /// Clear the match flag of the scheduler channel
+  fn clear_match(&self);
+  /// Check whether the scheduler channel has matched
+  fn is_matched(&self) -> bool;
+}
+
+/// The [RegisterAccess] to the compare channel of the system timer peripheral used by the scheduler
+pub struct SystemTimerAccess;
+
+impl RegisterAccess for SystemTimerAccess {
+  fn set_compare(&self, value: u32) {
+    channel::set_compare(value);
+  }
+
+  fn clear_match(&self) {
+    channel::clear_match();
+  }
+
+  fn is_matched(&self) -> bool {
+    channel::is_matched()
+  }
+}
+
+/// The register access currently used by the scheduler
+static REGISTER_ACCESS: Singleton<&'static dyn RegisterAccess> = Singleton::new(&SystemTimerAccess);
+
+/// Replace the [RegisterAccess] used by the scheduler to program its compare channel
+pub fn set_register_access(access: &'static dyn RegisterAccess) {
+  // the register access is used by the timer interrupt, so it must not be raised while it is replaced
+  irq_masked(|| REGISTER_ACCESS.with_mut(|current| *current = access));
+}
+
+/// Set the compare value of the scheduler channel with the active [RegisterAccess]
+fn set_compare(value: u32) {
+  REGISTER_ACCESS.with_ref(|access| access.set_compare(value));
+}
+
+/// Clear the match flag of the scheduler channel with the active [RegisterAccess]
+#[cfg(feature = "irq-schedule")]
+fn clear_match() {
+  REGISTER_ACCESS.with_ref(|access| access.clear_match());
+}
+
+/// Check whether the scheduler channel has matched with the active [RegisterAccess]
+#[cfg(feature = "irq-schedule")]
+fn is_matched() -> bool {
+  REGISTER_ACCESS.with_ref(|access| access.is_matched())
+}
+
+type ScheduledFunction = InlineFunction;
+/// The key of a function in the schedule list, the due time followed by the sequence number telling apart the
+/// functions due at the same time. The sequence increases with each function stored, so functions due at the same
+/// time are executed in the order they have been scheduled.
+#[cfg(not(feature = "timing-wheel"))]
+type ScheduleKey = (Duration, u64);
+#[cfg(not(feature = "timing-wheel"))]
+type FunctionScheduleList = BTreeMap<ScheduleKey, StoredFunction>;
+
+/// A function stored in the schedules together with the generation of the [ScheduleHandle] referring to it. The
+/// generation tells the function apart from a newer one that is stored with the same due time after the first one
+/// has been executed or cancelled. Functions scheduled without a handle have the generation 0.
+struct StoredFunction {
+  generation: u64,
+  function: ScheduledFunction,
+  /// The execution flag shared with the handle, to pass an equal handle to the dispatch hooks
+  #[cfg(feature = "dispatch-hooks")]
+  fired: Option<Arc<AtomicBool>>,
+  /// The flag to stop a function repeated by [schedule_periodic_aligned] shared with the handle
+  #[cfg(feature = "dispatch-hooks")]
+  cancelled: Option<Arc<AtomicBool>>,
+}
+
+impl StoredFunction {
+  /// Wrap a function no handle refers to
+  fn untracked(function: ScheduledFunction) -> Self {
+    Self {
+      generation: 0,
+      function,
+      #[cfg(feature = "dispatch-hooks")]
+      fired: None,
+      #[cfg(feature = "dispatch-hooks")]
+      cancelled: None,
+    }
+  }
+
+  /// The handle passed to the dispatch hooks for this function due at the given time. It is equal to the handle
+  /// returned when the function has been scheduled. Functions scheduled without a handle get one that is not equal
+  /// to any other.
+  #[cfg(feature = "dispatch-hooks")]
+  fn handle(&self, due: Duration) -> ScheduleHandle {
+    ScheduleHandle {
+      due,
+      generation: self.generation,
+      fired: self
+        .fired
+        .clone()
+        .unwrap_or_else(|| Arc::new(AtomicBool::new(true))),
+      cancelled: self.cancelled.clone(),
+    }
+  }
+}
+
+/// The generation given to the next function a [ScheduleHandle] is created for
+static GENERATION: AtomicU64 = AtomicU64::new(1);
+
+/// Handle to a function that has been scheduled for delayed execution
+#[derive(Debug)]
+pub struct ScheduleHandle {
+  /// The due time of the function the schedule list is sorted by
+  due: Duration,
+  /// The generation of the function, so the handle never refers to a different function stored with the same due time
+  generation: u64,
+  /// Flag set once the function is executed. It is shared with the scheduled function, so as long as the function
+  /// is pending there are two references to it
+  fired: Arc<AtomicBool>,
+  /// Flag set when a function repeated by [schedule_periodic_aligned] is cancelled. The repeated function checks it
+  /// before it is scheduled for the next period, so a cancel while it is executed stops it as well
+  cancelled: Option<Arc<AtomicBool>>,
+}
+
+impl ScheduleHandle {
+  /// Create the handle for the given function and wrap the function to flag its execution to the handle
+  fn track<F: FnOnce() + 'static + Send>(function: F) -> (StoredFunction, Arc<AtomicBool>) {
+    let fired = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&fired);
+    let function = ScheduledFunction::new(move || {
+      flag.store(true, Ordering::Release);
+      function();
+    });
+    let generation = GENERATION.fetch_add(1, Ordering::AcqRel);
+
+    (
+      StoredFunction {
+        generation,
+        function,
+        #[cfg(feature = "dispatch-hooks")]
+        fired: Some(Arc::clone(&fired)),
+        #[cfg(feature = "dispatch-hooks")]
+        cancelled: None,
+      },
+      fired,
+    )
+  }
+
+  /// Check whether the function is still waiting to be executed. This is ``false`` once the function has been
+  /// executed, cancelled or dropped as the scheduler has been shut down.
+  /// # Example
+  /// ```no_run
+  /// # use ruspiro_timer::*;
+  /// # fn doc() {
+  /// let fires = schedule_at(now() + Duration::from_millis(10), || ());
+  /// let cancelled = schedule_at(now() + Duration::from_millis(10), || ());
+  /// assert!(fires.is_pending() && cancelled.is_pending());
+  /// cancel(&cancelled);
+  /// assert!(!cancelled.is_pending() && !cancelled.has_fired());
+  /// sleep(Duration::from_millis(20));
+  /// assert!(!fires.is_pending() && fires.has_fired());
+  /// # }
+  /// ```
+  pub fn is_pending(&self) -> bool {
+    // the scheduler holds the second reference to the flag as long as the function has been neither executed nor
+    // dropped
+    !self.has_fired() && Arc::strong_count(&self.fired) > 1
+  }
+
+  /// Check whether the function has been executed. This is also ``true`` while the function is still running.
+  pub fn has_fired(&self) -> bool {
+    self.fired.load(Ordering::Acquire)
+  }
+}
+
+impl PartialEq for ScheduleHandle {
+  fn eq(&self, other: &Self) -> bool {
+    self.due == other.due
+      && self.generation == other.generation
+      && Arc::ptr_eq(&self.fired, &other.fired)
+  }
+}
+
+impl Eq for ScheduleHandle {}
+
+/// Guard for a scheduled function returned by [schedule_scoped]. The pending function is cancelled once the guard is
+/// dropped, unless it has been detached with [ScheduleGuard::detach]. Dropping the guard after the function has
+/// been executed already has no effect.
+#[derive(Debug)]
+pub struct ScheduleGuard {
+  handle: Option<ScheduleHandle>,
+}
+
+impl ScheduleGuard {
+  /// Detach the scheduled function from this guard so it will be executed even after the guard is gone. The handle
+  /// of the scheduled function is returned and can still be used to [cancel] it.
+  pub fn detach(mut self) -> ScheduleHandle {
+    // the handle is always present until the guard is detached or dropped
+    self.handle.take().unwrap()
+  }
+}
+
+impl Drop for ScheduleGuard {
+  fn drop(&mut self) {
+    if let Some(handle) = self.handle.take() {
+      cancel(&handle);
+    }
+  }
+}
+
+/// Handle to a function scheduled with [spawn_after] to check for or wait on its completion
+#[derive(Debug)]
+pub struct JoinHandle {
+  handle: ScheduleHandle,
+  /// Flag set once the function has returned
+  done: Arc<AtomicBool>,
+}
+
+impl JoinHandle {
+  /// Check whether the function has been executed and returned
+  pub fn is_done(&self) -> bool {
+    self.done.load(Ordering::Acquire)
+  }
+
+  /// Block until the function has been executed and returned. The due functions are polled while waiting, so this
+  /// also works without the feature ``irq-schedule``. Returns ``false`` if the function will never be executed as
+  /// it has been dropped while the scheduler has been shut down or cleared. This must not be called from within a
+  /// scheduled function, as the due functions are not executed from there.
+  pub fn wait(&self) -> bool {
+    loop {
+      if self.is_done() {
+        return true;
+      }
+      if !self.handle.is_pending() && !self.handle.has_fired() {
+        return false;
+      }
+      poll_schedules();
+      spin_loop();
+    }
+  }
+}
+
+/// Statistics about the execution of the scheduled functions as returned by [schedule_stats]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduleStats {
+  /// Number of scheduled functions that have been executed
+  pub fired: u64,
+  /// The maximum time a scheduled function has been executed after it was due
+  pub max_lateness: Duration,
+  /// Number of scheduled functions that have been executed later than the threshold set with
+  /// [set_overrun_threshold] after they were due
+  pub overruns: u64,
+}
+
+/// Number of scheduled functions that have been executed
+static FIRED: AtomicU64 = AtomicU64::new(0);
+
+/// The maximum lateness in nano seconds a scheduled function has been executed with
+static MAX_LATENESS: AtomicU64 = AtomicU64::new(0);
+
+/// The minimum time the compare value of the scheduler channel is set ahead of the counter. Functions scheduled with
+/// a shorter delay are still executed, but the interrupt is raised no earlier than this margin after the compare
+/// value has been set, so this is the minimum delay that is reliably met.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicBool, Ordering};
+/// static FIRED: AtomicBool = AtomicBool::new(false);
+///
+/// # fn doc() {
+/// // the delay has passed before the compare value is set, the function is executed nevertheless
+/// schedule(Duration::from_nanos(100), || FIRED.store(true, Ordering::Release));
+/// sleep(MIN_SCHEDULE_MARGIN * 10);
+/// assert!(FIRED.load(Ordering::Acquire));
+/// # }
+/// ```
+pub const MIN_SCHEDULE_MARGIN: Duration = Duration::from_micros(2);
+
+/// The shortest delay [try_schedule] accepts. Shorter delays can not be met reliably, as the compare value is set at
+/// least [MIN_SCHEDULE_MARGIN] ahead of the counter.
+pub const MIN_SCHEDULE_DELAY: Duration = MIN_SCHEDULE_MARGIN;
+
+/// The longest delay [try_schedule] accepts. The compare value only matches the lower 32Bit of the free running
+/// counter, so a longer delay would first match before the function is due. This is a little more than 71 minutes
+/// at the default [timer_frequency] of 1MHz.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// assert_eq!(max_schedule_delay(), Duration::from_micros(u32::MAX as u64));
+/// # }
+/// ```
+pub fn max_schedule_delay() -> Duration {
+  ticks_to_duration(u32::MAX as u64)
+}
+
+/// Number of scheduled functions executed later than the overrun threshold
+static OVERRUNS: AtomicU64 = AtomicU64::new(0);
+
+/// The lateness in nano seconds a scheduled function need to exceed to be counted as overrun, defaults to 1ms
+static OVERRUN_THRESHOLD: AtomicU64 = AtomicU64::new(1_000_000);
+
+/// The time in nano seconds a scheduled function may take before the hook set with [on_isr_overrun] is called.
+/// Defaults to no budget at all.
+static ISR_BUDGET: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// The hook called if a scheduled function exceeded the budget set with [set_isr_budget]
+static ISR_OVERRUN_HOOK: Singleton<Option<fn(Duration)>> = Singleton::new(None);
+
+#[cfg(feature = "dispatch-hooks")]
+type PreDispatchHook = fn(&ScheduleHandle);
+#[cfg(feature = "dispatch-hooks")]
+type PostDispatchHook = fn(&ScheduleHandle, Duration);
+
+/// The hook called right before a scheduled function is executed
+#[cfg(feature = "dispatch-hooks")]
+static PRE_DISPATCH_HOOK: Singleton<Option<PreDispatchHook>> = Singleton::new(None);
+
+/// The hook called right after a scheduled function has been executed with the time it took
+#[cfg(feature = "dispatch-hooks")]
+static POST_DISPATCH_HOOK: Singleton<Option<PostDispatchHook>> = Singleton::new(None);
+
+/// The time in nano seconds the interrupt for a due function may be delayed to execute further functions with it
+static COALESCE_WINDOW: AtomicU64 = AtomicU64::new(0);
+
+/// The maximum number of pending functions accepted by [try_schedule]
+static SCHEDULE_CAPACITY: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Flag for each core indicating that it is executing the due functions, so a nested timer interrupt must not
+/// start executing them as well
+static RUNNING_DUE: [AtomicBool; CORES] = [
+  AtomicBool::new(false),
+  AtomicBool::new(false),
+  AtomicBool::new(false),
+  AtomicBool::new(false),
+];
+
+/// Flag for each core indicating that a nested timer interrupt has been raised while the core was executing the due
+/// functions, so they need to be re-evaluated
+static RERUN_DUE: [AtomicBool; CORES] = [
+  AtomicBool::new(false),
+  AtomicBool::new(false),
+  AtomicBool::new(false),
+  AtomicBool::new(false),
+];
+
+/// The nesting depth of scheduled functions currently executed on each core
+static DISPATCH_DEPTH: [AtomicUsize; CORES] = [
+  AtomicUsize::new(0),
+  AtomicUsize::new(0),
+  AtomicUsize::new(0),
+  AtomicUsize::new(0),
+];
+
+/// Entry of the intake queue of functions that have been scheduled from within a scheduled function
+struct IntakeEntry {
+  due: Duration,
+  function: StoredFunction,
+  next: *mut IntakeEntry,
+}
+
+/// Lock free queue of the functions that have been scheduled from within a scheduled function. Pushing to it never
+/// waits for the lock of the schedules, so it is safe from within the timer interrupt. It is only drained with the
+/// IRQ masked mutual exclusive access to the schedules.
+static INTAKE: AtomicPtr<IntakeEntry> = AtomicPtr::new(ptr::null_mut());
+
+/// Flag indicating that the interrupt of the scheduler channel has been activated
+static IRQ_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Flag indicating that the execution of the scheduled functions has been paused with [pause_scheduler]
+static PAUSED: AtomicBool = AtomicBool::new(false);
 
 /// Structure to contain the data needed to "manage" the functions to be scheduled
+#[cfg(not(feature = "timing-wheel"))]
 struct Schedules {
   /// Timer value for the very next function to be executed
   pub next_due: AtomicU64,
-  /// Sorted list of function to be executed. The key is the timer value when they are due
+  /// Sorted list of function to be executed. The key is the timer value when they are due and their sequence
   pub schedule_list: FunctionScheduleList,
-  /// index into the schedule list pointing to the next due entry that will be executed once
-  /// the timer interrupt is triggered the next time
-  pub due_index: AtomicUsize,
-  /// index into the schedule list pointing to the last already done entry. This is used to check
-  /// whether it would be safe to shrink the schedule list to get rid of all the already executed
-  /// functions to reduce memory consumption
-  pub done_index: AtomicUsize,
+  /// The sequence number given to the next function stored in the schedule list
+  pub sequence: u64,
+  /// number of functions in the schedule list that have not been executed yet
+  pub pending: AtomicUsize,
+}
+
+/// Structure to contain the data needed to "manage" the functions to be scheduled using the timing wheel. The wheel
+/// is only accessed with mutual exclusive access to the schedules
+#[cfg(feature = "timing-wheel")]
+struct Schedules {
+  /// Timer value for the very next function to be executed
+  pub next_due: AtomicU64,
+  /// The functions to be executed hashed by the timer value when they are due
+  pub wheel: TimingWheel<StoredFunction>,
+  /// The sequence number given to the next function stored in the timing wheel
+  pub sequence: u64,
+  /// number of functions in the timing wheel that have not been executed yet
+  pub pending: AtomicUsize,
+}
+
+#[cfg(feature = "timing-wheel")]
+impl Schedules {
+  /// Create a new instance holding the schedule information
+  fn new() -> Self {
+    Self {
+      next_due: AtomicU64::new(0),
+      wheel: TimingWheel::new(now()),
+      sequence: 0,
+      pending: AtomicUsize::new(0),
+    }
+  }
+
+  /// Take the earliest function out of the timing wheel if it is due
+  fn pop_due(&mut self) -> Option<(Duration, StoredFunction)> {
+    self.wheel.pop_due(now())
+  }
+
+  /// The due time of the earliest function in the timing wheel
+  fn earliest(&self) -> Option<Duration> {
+    self.wheel.next_due()
+  }
+
+  /// The due times of all functions in the timing wheel, the earliest first
+  fn due_times(&self) -> Vec<Duration> {
+    let mut due_times: Vec<_> = self.wheel.due_times().collect();
+    due_times.sort_unstable();
+    due_times
+  }
 }
 
+#[cfg(not(feature = "timing-wheel"))]
 impl Schedules {
   /// Create a new instance holding the schedule information
   fn new() -> Self {
     Self {
       next_due: AtomicU64::new(0),
       schedule_list: BTreeMap::new(),
-      due_index: AtomicUsize::new(0),
-      done_index: AtomicUsize::new(0),
+      sequence: 0,
+      pending: AtomicUsize::new(0),
     }
   }
 
-  /// Shrink the list of scheduled functions to get rid of all what has been processed already.
-  ///
-  /// # Safety
-  /// This is safe when this function is called when it is ensured that no concurrent processing
-  /// tries actually to index into the values or keys of the list, while shrinking. A typical
-  /// scenario would be, when we are about to add a new entry to the list and see that the index
-  /// for done items is equal to the index of due items, which means that there will be no interrupt
-  /// triggered that may want to execute a scheduled function.
-  unsafe fn shrink(&mut self) {
-    self.schedule_list.clear();
-    // after removing the scheduled functions we can reset the due and done indices
-    // as well as the next due value to ensure the first scheduled function will set the
-    // appropriate value
-    self.next_due.store(0, Ordering::Release);
-    self.due_index.store(0, Ordering::Release);
-    self.done_index.store(0, Ordering::Release);
+  /// Take the earliest function out of the schedule list if it is due
+  fn pop_due(&mut self) -> Option<(Duration, StoredFunction)> {
+    let key = *self.schedule_list.keys().next()?;
+    if !is_due(key.0) {
+      return None;
+    }
+    self
+      .schedule_list
+      .remove(&key)
+      .map(|function| (key.0, function))
+  }
+
+  /// The due time of the earliest function in the schedule list
+  fn earliest(&self) -> Option<Duration> {
+    self.schedule_list.keys().next().map(|&(due, _)| due)
+  }
+
+  /// The due times of all functions in the schedule list, the earliest first
+  fn due_times(&self) -> Vec<Duration> {
+    self.schedule_list.keys().map(|&(due, _)| due).collect()
   }
 }
 
-// TODO: is it really safe to say Schedules are Send and Sync just the way they are defined?
-unsafe impl Send for Schedules {}
+// The schedule list or timing wheel is only accessed with mutual exclusive access to the schedules, the shared access
+// is only used to read the atomic values. So it's safe to share the schedules even though the stored functions are
+// not ``Sync``
 unsafe impl Sync for Schedules {}
 
 /// The global static carrying the list of scheduled functions. The type looks a bit arkward at first
 /// look but is needed to fulfill the following reqirements and constrains
 /// 1. We need mutual exclusive access to the sorted list to add new scheduled functions to it
-/// 2. Mutual exclusive access should not be needed while inside the interrupt handler to circumvent
-///    deadlock situations
-/// 3. Even though the interrupt handler has no mutual exclusive access to the whole list it would need
-///    mutual exclusive access to the stored function to consume it while calling
+/// 2. Waiting for the mutual exclusive access inside the interrupt handler must never deadlock. The handler takes
+///    the earliest due function out of the list with mutual exclusive access as well, so the access is only ever
+///    taken with the IRQ of the current core masked, see [schedules_mut] and [schedules_ref]. The interrupt can
+///    therefore never be raised on a core that holds the access, the handler only waits for the short critical
+///    section of another core to end
+/// 3. The access is released before a due function is called, so the function may schedule new ones
 /// 4. As the BTreeMap's new function is not a const one we need to wrap it with a Option
 static SCHEDULE: Singleton<Option<Schedules>> = Singleton::new(None);
 
+/// Get mutual exclusive access to the schedules with the IRQ of the current core masked, so the timer interrupt can
+/// not be raised on this core while the access is held. All accesses to [SCHEDULE] need to go through this function
+/// or [schedules_ref].
+fn schedules_mut<R>(f: impl FnOnce(&mut Option<Schedules>) -> R) -> R {
+  irq_masked(|| SCHEDULE.with_mut(f))
+}
+
+/// Get shared access to the schedules with the IRQ of the current core masked, see [schedules_mut]. Even a shared
+/// access would keep the interrupt handler on the same core from getting the mutual exclusive one.
+fn schedules_ref<R>(f: impl FnOnce(&Option<Schedules>) -> R) -> R {
+  irq_masked(|| SCHEDULE.with_ref(f))
+}
+
 /// Schedule a function for delayed execution with a millisecond offset relative to the time of the
 /// execution of this function.
 /// ## Hint:
@@ -93,6 +675,13 @@ static SCHEDULE: Singleton<Option<Schedules>> = Singleton::new(None);
 /// a heavy processing can continue in the context outside the actual interrupt using a ``Semaphore``
 /// or a ``Channel``
 ///
+/// The function is due after the given delay, even if it is shorter than [MIN_SCHEDULE_MARGIN]. Only the compare
+/// value of the timer is set at least this margin ahead, as it could otherwise already have passed once it has been
+/// set, so the interrupt of such a short delay is raised no earlier than the margin.
+///
+/// Closures capturing up to 24 bytes, like a reference or a counter, are stored inline in the schedules. Only larger
+/// closures are boxed and cost an additional heap allocation.
+///
 /// # Example
 /// ```no_run
 /// # use ruspiro_timer::*;
@@ -101,12 +690,12 @@ static SCHEDULE: Singleton<Option<Schedules>> = Singleton::new(None);
 ///     let mut counter = 10;
 ///     // schedule a function that prints the value of the variable at the time of beeing scheduled
 ///     // after 1 second
-///     schedule(Mseconds(1_000), move || println!("Value when scheduled: {}", counter));
+///     schedule(Duration::from_millis(1_000), move || println!("Value when scheduled: {}", counter));
 ///     counter += 10;
 ///     // print the actual value of the variable as processing continues
 ///     println!("actual value: {}", counter);
-///     // sleep to wait for the scheduled function to get executed (this is in micro seconds!)
-///     sleep(Useconds(1_500_000));
+///     // sleep to wait for the scheduled function to get executed
+///     sleep(Duration::from_micros(1_500_000));
 /// # }
 /// ```
 /// The expected output of this example would be:
@@ -114,107 +703,1944 @@ static SCHEDULE: Singleton<Option<Schedules>> = Singleton::new(None);
 /// actual value: 20
 /// Value when scheduled: 10
 /// ```
+/// A small closure is scheduled without an allocation:
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+/// # use std::alloc::{GlobalAlloc, Layout, System};
+/// // an allocator counting the allocations
+/// struct Counting;
+///
+/// static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+///
+/// unsafe impl GlobalAlloc for Counting {
+///   unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+///     ALLOCATIONS.fetch_add(1, Ordering::AcqRel);
+///     System.alloc(layout)
+///   }
+///   unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+///     System.dealloc(ptr, layout)
+///   }
+/// }
+///
+/// #[global_allocator]
+/// static GLOBAL: Counting = Counting;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// static FIRED: AtomicBool = AtomicBool::new(false);
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// // the first function creates the schedules
+/// schedule(Duration::from_millis(20), || ());
+///
+/// let before = ALLOCATIONS.load(Ordering::Acquire);
+/// schedule(Duration::from_millis(10), || FIRED.store(true, Ordering::Release));
+/// assert_eq!(ALLOCATIONS.load(Ordering::Acquire), before);
+///
+/// // a closure capturing more than 24 bytes is boxed
+/// let large = [0u64; 4];
+/// schedule(Duration::from_millis(30), move || assert_eq!(large.len(), 4));
+/// assert_eq!(ALLOCATIONS.load(Ordering::Acquire), before + 1);
+///
+/// CLOCK.advance(10_000);
+/// poll_schedules();
+/// assert!(FIRED.load(Ordering::Acquire));
+/// # }
+/// ```
 pub fn schedule<F: FnOnce() + 'static + Send>(delay: Duration, function: F) {
   // calculate the time this function shall be scheduled based on the current time and the
   // requested delay given in milli seconds
-  let due = now() + delay;
+  let current = now();
+  // saturating like [now_plus], but the same snapshot of the time is used to decide about re-arming the timer
+  let due = current.saturating_add(delay);
   // take the list and add the new entry
-  SCHEDULE.with_mut(|schedules: &mut Option<Schedules>| {
-    if schedules.is_none() {
-      // when the first function get's to be scheduled create the new sorted list
-      schedules.replace(Schedules::new());
-      // than clear the match flag from the control register after otherwise the interrupt might
-      // be immediately triggered when activated as the initial value might immidiately match the
-      // timer value ...
-      SYS_TIMERCS::Register.write_value(SYS_TIMERCS::M1::MATCH);
-      // and activate the timer interrupts to be dispatched
-      irq::activate(Interrupt::SystemTimer1, None);
-    }
-
-    if let Some(ref mut schedules) = schedules.as_mut() {
-      // before inserting a new scheduled function check if we could shrink the list
-      // get the last due and done index
-      let due_index = schedules.due_index.load(Ordering::Relaxed) - 1;
-      let done_index = schedules.done_index.load(Ordering::Relaxed);
-      // if something has been done already and we are done with all that have been due it is
-      // safe to shrink the list
-      if done_index > 0 && due_index == done_index {
-        // as we have mutual exclusive access here there is no other way items could be added
-        // so once the done index equals the due index we can safely shrink the list
-        unsafe {
-          schedules.shrink();
-        }
-      };
+  enqueue(
+    due,
+    StoredFunction::untracked(ScheduledFunction::new(function)),
+    current,
+  );
+}
 
-      schedules
-        .schedule_list
-        .insert(due, UnsafeCell::new(Some(Box::new(function))));
-      // now that we have added the new function check if we need to adjust the already set match
-      // value for the interrupt to be raised
-      let next_due = Duration::from_micros(schedules.next_due.load(Ordering::Acquire));
-      // on first entry, when the current next due is after the new due
-      // or when the current next_due is already in the past, set a new next due
-      if next_due.is_zero() || due < next_due || next_due < now() {
-        schedules
-          .next_due
-          .store(due.as_micros() as u64, Ordering::Release);
-        SYS_TIMERC1::Register.set(due.as_micros() as u32);
-      };
-    };
+/// Schedule a function for execution at the given absolute time as returned by [now]. This allows to align several
+/// functions to the same point in time without the jitter of calculating a relative delay for each of them. If the
+/// deadline is already in the past the function is due immediately and will be executed with the next timer
+/// interrupt.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+///     let deadline = now() + Duration::from_millis(100);
+///     // both functions will be due at exactly the same time
+///     schedule_at(deadline, || println!("first"));
+///     schedule_at(deadline, || println!("second"));
+/// # }
+/// ```
+pub fn schedule_at<F: FnOnce() + 'static + Send>(
+  deadline: Duration,
+  function: F,
+) -> ScheduleHandle {
+  // a deadline in the past is treated as due right now, this ensures the new entry is not sorted before the ones
+  // that have been executed already
+  let current = now();
+  let due = deadline.max(current);
+  let (function, fired) = ScheduleHandle::track(function);
+  let generation = function.generation;
+  enqueue(due, function, current);
+
+  ScheduleHandle {
+    due,
+    generation,
+    fired,
+    cancelled: None,
+  }
+}
+
+/// Schedule a batch of functions for delayed execution like [schedule], each with its own delay. All functions are
+/// inserted with a single access to the schedules and the compare value of the timer is set only once for the
+/// soonest of them. This is cheaper than scheduling the functions one after another, e.g. when registering a set of
+/// tasks at boot time.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+/// struct Spy {
+///   compare: AtomicU32,
+///   writes: AtomicUsize,
+/// }
+///
+/// impl RegisterAccess for Spy {
+///   fn set_compare(&self, value: u32) {
+///     self.compare.store(value, Ordering::Release);
+///     self.writes.fetch_add(1, Ordering::AcqRel);
+///   }
+///   fn clear_match(&self) {}
+///   fn is_matched(&self) -> bool {
+///     false
+///   }
+/// }
+///
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// static SPY: Spy = Spy { compare: AtomicU32::new(0), writes: AtomicUsize::new(0) };
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// set_register_access(&SPY);
+/// // 20 functions due after 20ms, 19ms ... 1ms
+/// let tasks = (1..=20).rev().map(|i| {
+///   let task: Box<dyn FnOnce() + Send> = Box::new(|| ());
+///   (Duration::from_millis(i), task)
+/// });
+/// schedule_many(tasks);
+/// assert_eq!(pending_schedule_count(), 20);
+/// // the compare value has been set once for the soonest function
+/// assert_eq!(SPY.writes.load(Ordering::Acquire), 1);
+/// assert_eq!(SPY.compare.load(Ordering::Acquire), 1_000);
+/// # }
+/// ```
+pub fn schedule_many<I>(items: I)
+where
+  I: IntoIterator<Item = (Duration, Box<dyn FnOnce() + Send>)>,
+{
+  let current = now();
+  let items = items.into_iter().map(|(delay, function)| {
+    (
+      current.saturating_add(delay),
+      StoredFunction::untracked(ScheduledFunction::new(function)),
+    )
+  });
+  if is_dispatching() {
+    for (due, function) in items {
+      push_intake(due, function);
+    }
+    return;
+  }
+
+  schedules_mut(|schedules| {
+    drain_intake(schedules);
+    let soonest = items
+      .map(|(due, function)| {
+        store(schedules, due, function);
+        due
+      })
+      .min();
+    if let (Some(schedules), Some(soonest)) = (schedules.as_ref(), soonest) {
+      rearm(schedules, soonest, current);
+    }
   });
 }
 
-/// Implement the timer interrupt handler for interrupt based timed execution
-#[IrqHandler(SystemTimer1)]
-unsafe fn timer_handler(channel: Option<IsrSender<Box<dyn Any>>>) {
-  // check which timer compare/match value has raised this interrupt, only care on number 1 ...
-  if SYS_TIMERCS::Register.read(SYS_TIMERCS::M1) == 1 {
-    // first acknowledge the timer interrupt by writing 1 to the match register value
-    SYS_TIMERCS::Register.write_value(SYS_TIMERCS::M1::MATCH);
-    // use the list to find the the entry we should execute now, as it is sorted we start from
-    // the front, the actual index into the list is atomically stored to ensure even we can not
-    // have mutual exclusive access to the list
-    SCHEDULE.with_ref(|schedules: &Option<Schedules>| {
-      if let Some(ref schedules) = schedules {
-        let next_idx = schedules.due_index.fetch_add(1, Ordering::AcqRel);
-        if next_idx >= schedules.schedule_list.len() {
-          return;
-        }
-
-        let functions: Vec<_> = schedules.schedule_list.values().collect();
-        let function_cell = functions[next_idx];
-        // now we have the cell containing the function to be called
-        // accessing this mutably is safe as we are now the only one accessing this entry
-        // due to the fact that we have atomically adjusted the index into the list, so any
-        // other core will use a different index...
-        let function = function_cell.get();
-        // take the function out of the option
-        let function_to_call = (*function).take().unwrap();
-        // call the function
-        (function_to_call)();
-        // in case there is already another function scheduled in the list retrieve it's due
-        // time and setup the next match value
-        if schedules.schedule_list.len() > next_idx + 1 {
-          // this is safe here as the list of scheduled functions only grows with one
-          // exception, when the a new entry is about to e added while all other are already
-          // processed
-          let due_list: Vec<_> = schedules.schedule_list.keys().collect();
-          let next_due = due_list[next_idx + 1];
-          // TOCHECK: setting the next due from the list contains a small uncertainty as this
-          // interrupt might have interferred the insertion of a scheduled functions that was
-          // scheduled with a due time smaller than the one just retrieved from the list
-          // this lead to a very tiny possibility that the next trigger value is not set
-          // properly. However, as scheduling is only possible with a minimal delay of 1ms
-          // this window, smaller than a micro-second should never occur
-          SYS_TIMERC1::Register.set(next_due.as_micros() as u32);
-          schedules
-            .next_due
-            .store(next_due.as_micros() as u64, Ordering::SeqCst);
-        }
-        // as we have executed this function and are don with all related updates we can update
-        // the index of the done functions
-        schedules.done_index.store(next_idx, Ordering::Release);
-      }
-    });
+/// Schedule a function to be executed repeatedly with the given period until it returns ``ControlFlow::Break``. This
+/// is the scheduled counterpart of [every], so the function can stop itself without keeping a handle to it. The first
+/// call is due one period from now and the n-th call at ``start + n * period``, so the time spent inside the function
+/// does not add up to a drift. If the function has been executed late by one or more periods the missed calls are
+/// skipped. A period of zero is extended to one tick of the timer.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::ops::ControlFlow;
+/// # use core::sync::atomic::{AtomicUsize, Ordering};
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// static RUNS: AtomicUsize = AtomicUsize::new(0);
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// schedule_repeating(Duration::from_millis(10), || {
+///   // stop after the third run
+///   if RUNS.fetch_add(1, Ordering::AcqRel) + 1 < 3 {
+///     ControlFlow::Continue(())
+///   } else {
+///     ControlFlow::Break(())
+///   }
+/// });
+/// for _ in 0..5 {
+///   CLOCK.advance(10_000);
+///   poll_schedules();
+/// }
+/// assert_eq!(RUNS.load(Ordering::Acquire), 3);
+/// assert_eq!(pending_schedule_count(), 0);
+/// # }
+/// ```
+pub fn schedule_repeating<F>(period: Duration, function: F)
+where
+  F: FnMut() -> ControlFlow<()> + 'static + Send,
+{
+  let period = period.max(ticks_to_duration(1));
+  repeat_at(now().saturating_add(period), period, function);
+}
+
+/// Schedule the repeated function for its next call at the given time
+fn repeat_at<F>(due: Duration, period: Duration, mut function: F)
+where
+  F: FnMut() -> ControlFlow<()> + 'static + Send,
+{
+  let current = now();
+  let repeat = ScheduledFunction::new(move || {
+    if let ControlFlow::Break(()) = function() {
+      return;
+    }
+    repeat_at(next_period(due, period), period, function);
+  });
+  enqueue(due.max(current), StoredFunction::untracked(repeat), current);
+}
+
+/// The time the next call of a function repeated with the given period is due after the call due at the given time.
+/// If the call has been late by one or more periods the missed calls are skipped.
+fn next_period(due: Duration, period: Duration) -> Duration {
+  let mut next = due.saturating_add(period);
+  let current = now();
+  if next < current {
+    // the call has been late by at least one period, skip to the next boundary that is still in the future
+    let missed = (current - next).as_nanos() / period.as_nanos() + 1;
+    let skip = period
+      .checked_mul(missed.min(u32::MAX as u128) as u32)
+      .unwrap_or(Duration::MAX);
+    next = next.saturating_add(skip);
+  }
+
+  next
+}
+
+/// Schedule a function to be executed repeatedly on the boundaries of the given period, see [align_to]. The first
+/// call is due on the next boundary and all further calls on the following ones, so several functions scheduled
+/// this way at different times with the same period stay in phase with each other, e.g. to sample several sensors
+/// at the same time. If the function has been executed late by one or more periods the missed calls are skipped. A
+/// period of zero is extended to one tick of the timer.
+///
+/// The function is repeated until it is stopped by calling [cancel] with the returned handle, which is pending as
+/// long as the function is repeated.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicU64, Ordering};
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// static FIRST: AtomicU64 = AtomicU64::new(0);
+/// static SECOND: AtomicU64 = AtomicU64::new(0);
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// CLOCK.set(3_000);
+/// let first = schedule_periodic_aligned(Duration::from_millis(10), || {
+///   FIRST.store(now().as_millis() as u64, Ordering::Release);
+/// });
+/// // the second one is created 4ms later but fires on the same boundaries
+/// CLOCK.set(7_000);
+/// let second = schedule_periodic_aligned(Duration::from_millis(10), || {
+///   SECOND.store(now().as_millis() as u64, Ordering::Release);
+/// });
+/// for boundary in [10, 20, 30] {
+///   CLOCK.set(boundary * 1_000 + 1);
+///   poll_schedules();
+///   assert_eq!(FIRST.load(Ordering::Acquire), boundary);
+///   assert_eq!(SECOND.load(Ordering::Acquire), boundary);
+/// }
+///
+/// assert!(first.is_pending() && second.is_pending());
+/// assert!(cancel(&first) && cancel(&second));
+/// assert_eq!(pending_schedule_count(), 0);
+/// # }
+/// ```
+pub fn schedule_periodic_aligned<F>(period: Duration, function: F) -> ScheduleHandle
+where
+  F: FnMut() + 'static + Send,
+{
+  let period = period.max(ticks_to_duration(1));
+  let fired = Arc::new(AtomicBool::new(false));
+  let cancelled = Arc::new(AtomicBool::new(false));
+  let generation = GENERATION.fetch_add(1, Ordering::AcqRel);
+  let due = periodic_at(
+    align_to(period),
+    period,
+    generation,
+    Arc::clone(&fired),
+    Arc::clone(&cancelled),
+    function,
+  );
+
+  ScheduleHandle {
+    due,
+    generation,
+    fired,
+    cancelled: Some(cancelled),
+  }
+}
+
+/// Schedule the periodic function for its next call at the given time. Each call is stored with the same generation,
+/// and the flag of the handle is kept by the function, so the handle refers to it as long as it is repeated. The flag
+/// itself is never set, as the function is never done. Once the ``cancelled`` flag is set the function is neither
+/// called nor scheduled again, even if it has been taken out of the schedules for its execution already. Returns the
+/// due time the call is stored with, which is now if the given time has passed already.
+fn periodic_at<F>(
+  due: Duration,
+  period: Duration,
+  generation: u64,
+  _fired: Arc<AtomicBool>,
+  cancelled: Arc<AtomicBool>,
+  mut function: F,
+) -> Duration
+where
+  F: FnMut() + 'static + Send,
+{
+  let current = now();
+  #[cfg(feature = "dispatch-hooks")]
+  let flag = Some(Arc::clone(&_fired));
+  #[cfg(feature = "dispatch-hooks")]
+  let stop = Some(Arc::clone(&cancelled));
+  let repeat = ScheduledFunction::new(move || {
+    if cancelled.load(Ordering::Acquire) {
+      return;
+    }
+    function();
+    // the function might have been cancelled while it was executed
+    if cancelled.load(Ordering::Acquire) {
+      return;
+    }
+    periodic_at(
+      next_period(due, period),
+      period,
+      generation,
+      _fired,
+      cancelled,
+      function,
+    );
+  });
+  let stored = StoredFunction {
+    generation,
+    function: repeat,
+    #[cfg(feature = "dispatch-hooks")]
+    fired: flag,
+    #[cfg(feature = "dispatch-hooks")]
+    cancelled: stop,
+  };
+
+  let due = due.max(current);
+  enqueue(due, stored, current);
+
+  due
+}
+
+/// Schedule a function for delayed execution like [schedule] and get a [JoinHandle] to know when it has finished.
+/// This bridges the fire-and-forget scheduling with code that needs to wait for the deferred work to complete.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+/// // a time source advancing 1µs with each read
+/// struct Ticking(AtomicU64);
+///
+/// impl TimeSource for Ticking {
+///   fn ticks(&self) -> u64 {
+///     self.0.fetch_add(1, Ordering::AcqRel)
+///   }
+/// }
+///
+/// static CLOCK: Ticking = Ticking(AtomicU64::new(0));
+/// static EXECUTED: AtomicBool = AtomicBool::new(false);
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// let job = spawn_after(Duration::from_millis(10), || EXECUTED.store(true, Ordering::Release));
+/// assert!(!job.is_done());
+/// // waiting returns only after the function has been executed
+/// assert!(job.wait());
+/// assert!(job.is_done() && EXECUTED.load(Ordering::Acquire));
+/// assert!(now() >= Duration::from_millis(10));
+/// # }
+/// ```
+pub fn spawn_after<F>(delay: Duration, function: F) -> JoinHandle
+where
+  F: FnOnce() + 'static + Send,
+{
+  let done = Arc::new(AtomicBool::new(false));
+  let flag = Arc::clone(&done);
+  let handle = schedule_at(now_plus(delay), move || {
+    function();
+    flag.store(true, Ordering::Release);
+  });
+
+  JoinHandle { handle, done }
+}
+
+/// Schedule a function for delayed execution like [schedule] and get the value it returns through a channel. The
+/// returned [Receiver] yields the value once the function has been executed, ``recv`` returns an error as long as
+/// this has not happened yet. This packages the pattern of signaling the completion of a scheduled function.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// let result = schedule_with_result(Duration::from_millis(10), || 6 * 7);
+/// assert!(result.recv().is_err());
+/// sleep(Duration::from_millis(20));
+/// assert_eq!(result.recv(), Ok(42));
+/// # }
+/// ```
+pub fn schedule_with_result<T, F>(delay: Duration, function: F) -> Receiver<T>
+where
+  T: Send + 'static,
+  F: FnOnce() -> T + 'static + Send,
+{
+  let (sender, receiver) = channel();
+  schedule(delay, move || sender.send(function()));
+  receiver
+}
+
+/// Schedule a function for delayed execution like [schedule] but respect the capacity set with
+/// [set_schedule_capacity] and validate the delay. If there are already as many functions pending as the capacity
+/// allows the function is dropped and [ScheduleError::Full] is returned. A delay shorter than [MIN_SCHEDULE_DELAY]
+/// or longer than [max_schedule_delay] is rejected with [ScheduleError::DelayOutOfRange]. On success the
+/// [ScheduleHandle] of the scheduled function is returned. When called from within a scheduled function the capacity
+/// is not checked.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+///     set_schedule_capacity(16);
+///     if try_schedule(Duration::from_millis(100), || println!("delayed")).is_err() {
+///       println!("too many pending functions");
+///     }
+/// # }
+/// ```
+/// The delay need to be within the range the scheduler can reliably meet:
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+/// assert_eq!(
+///   try_schedule(Duration::from_nanos(500), || ()).unwrap_err(),
+///   ScheduleError::DelayOutOfRange
+/// );
+/// assert_eq!(
+///   try_schedule(max_schedule_delay() + Duration::from_micros(1), || ()).unwrap_err(),
+///   ScheduleError::DelayOutOfRange
+/// );
+/// assert!(try_schedule(MIN_SCHEDULE_DELAY, || ()).is_ok());
+/// assert!(try_schedule(max_schedule_delay(), || ()).is_ok());
+/// # }
+/// ```
+pub fn try_schedule<F: FnOnce() + 'static + Send>(
+  delay: Duration,
+  function: F,
+) -> Result<ScheduleHandle, ScheduleError> {
+  if delay < MIN_SCHEDULE_DELAY || delay > max_schedule_delay() {
+    return Err(ScheduleError::DelayOutOfRange);
+  }
+  let current = now();
+  let due = current.saturating_add(delay);
+  let (function, fired) = ScheduleHandle::track(function);
+  let generation = function.generation;
+  if is_dispatching() {
+    // the capacity can not be checked without the access to the schedules
+    enqueue(due, function, current);
+    return Ok(ScheduleHandle {
+      due,
+      generation,
+      fired,
+      cancelled: None,
+    });
+  }
+  schedules_mut(|schedules| {
+    drain_intake(schedules);
+    let pending = schedules
+      .as_ref()
+      .map_or(0, |schedules| schedules.pending.load(Ordering::Acquire));
+    if pending >= SCHEDULE_CAPACITY.load(Ordering::Acquire) {
+      return Err(ScheduleError::Full);
+    }
+
+    insert(schedules, due, function, current);
+    Ok(ScheduleHandle {
+      due,
+      generation,
+      fired,
+      cancelled: None,
+    })
+  })
+}
+
+/// Schedule a function for delayed execution like [schedule] but tie it to the scope of the returned
+/// [ScheduleGuard]. If the guard is dropped before the function is due the function is cancelled. This is handy for
+/// timeouts that shall only fire if the current scope is not left in time.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+///     fn do_work() {
+///       // the watchdog only fires if this function does not return within 100ms
+///       let _watchdog = schedule_scoped(Duration::from_millis(100), || println!("work is stuck"));
+///       // do the actual work ...
+///     }
+///     do_work();
+///     // keep a scheduled function alive beyond the scope
+///     let handle = schedule_scoped(Duration::from_millis(100), || println!("fired")).detach();
+/// # }
+/// ```
+pub fn schedule_scoped<F: FnOnce() + 'static + Send>(
+  delay: Duration,
+  function: F,
+) -> ScheduleGuard {
+  let current = now();
+  let due = current.saturating_add(delay);
+  let (function, fired) = ScheduleHandle::track(function);
+  let generation = function.generation;
+  enqueue(due, function, current);
+
+  ScheduleGuard {
+    handle: Some(ScheduleHandle {
+      due,
+      generation,
+      fired,
+      cancelled: None,
+    }),
+  }
+}
+
+/// Cancel the scheduled function referred to by the given handle. Returns ``true`` if the function has been removed
+/// before it was executed and ``false`` if it has been executed or cancelled already. A function repeated by
+/// [schedule_periodic_aligned] that is executed while it is cancelled is not scheduled for its next period, this
+/// returns ``true`` as well.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+///     let handle = schedule_at(now() + Duration::from_secs(1), || println!("never printed"));
+///     assert!(cancel(&handle));
+///     assert!(!cancel(&handle));
+/// # }
+/// ```
+/// Each handle carries the generation of its function, so an outdated handle never cancels a newer function that
+/// has been stored with the same due time:
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicBool, Ordering};
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// static NEWER: AtomicBool = AtomicBool::new(false);
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// let due = Duration::from_millis(10);
+/// let older = schedule_at(due, || ());
+/// CLOCK.set(10_000);
+/// poll_schedules();
+/// // the newer function takes over the due time of the executed one
+/// let newer = schedule_at(due, || NEWER.store(true, Ordering::Release));
+/// assert!(older != newer);
+/// assert!(!cancel(&older));
+/// assert!(newer.is_pending());
+/// poll_schedules();
+/// assert!(NEWER.load(Ordering::Acquire));
+/// # }
+/// ```
+pub fn cancel(handle: &ScheduleHandle) -> bool {
+  // a function executed already might share its due time with a newer one that must not be cancelled
+  if !handle.is_pending() {
+    return false;
+  }
+  // a repeated function that is executed right now is not in the schedules, the flag keeps it from being scheduled
+  // for the next period
+  let stopped = match handle.cancelled {
+    Some(ref cancelled) => !cancelled.swap(true, Ordering::AcqRel),
+    None => false,
+  };
+  let removed = schedules_mut(|schedules| {
+    // the function might have been scheduled from within a scheduled function
+    drain_intake(schedules);
+    schedules
+      .as_mut()
+      .and_then(|schedules| take_function(schedules, handle.due, handle.generation))
+      .is_some()
+  });
+
+  removed || stopped
+}
+
+/// Move the still pending function referred to by the given handle to be due after the given delay from now. The
+/// function is moved as it is, without being dropped and scheduled again. The handle is updated to refer to the new
+/// due time. Returns ``false`` if the function has been executed or cancelled already.
+///
+/// This is the typical operation for watchdog or keepalive timeouts that are pushed further into the future each time
+/// the guarded activity shows a sign of life.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+///     let mut keepalive = schedule_at(now() + Duration::from_millis(100), || println!("connection lost"));
+///     // each time data is received push the timeout further
+///     assert!(reschedule(&mut keepalive, Duration::from_millis(100)));
+/// # }
+/// ```
+pub fn reschedule(handle: &mut ScheduleHandle, new_delay: Duration) -> bool {
+  if !handle.is_pending() {
+    return false;
+  }
+  let current = now();
+  let due = current.saturating_add(new_delay);
+  schedules_mut(|schedules| {
+    // the function might have been scheduled from within a scheduled function
+    drain_intake(schedules);
+    let function = match schedules
+      .as_mut()
+      .and_then(|schedules| take_function(schedules, handle.due, handle.generation))
+    {
+      Some(function) => function,
+      None => return false,
+    };
+    insert(schedules, due, function, current);
+    handle.due = due;
+
+    true
+  })
+}
+
+/// Take the pending function with the given generation out of the schedules. A different function stored with the
+/// same due time is left untouched. This requires mutual exclusive access to the schedules.
+fn take_function(
+  schedules: &mut Schedules,
+  due: Duration,
+  generation: u64,
+) -> Option<StoredFunction> {
+  let (due, sequence) = key_of(schedules, due, generation)?;
+  #[cfg(not(feature = "timing-wheel"))]
+  let function = schedules.schedule_list.remove(&(due, sequence));
+  #[cfg(feature = "timing-wheel")]
+  let function = schedules.wheel.remove(due, sequence);
+  if function.is_some() {
+    schedules.pending.fetch_sub(1, Ordering::AcqRel);
+  }
+
+  function
+}
+
+/// The due time and sequence the pending function with the given generation is stored with. The function is looked
+/// up among the functions due at the time the handle knows first. A function repeated by [schedule_periodic_aligned]
+/// has moved on to a later due time since, so it is searched for in all pending functions.
+fn key_of(schedules: &Schedules, due: Duration, generation: u64) -> Option<(Duration, u64)> {
+  #[cfg(not(feature = "timing-wheel"))]
+  {
+    let at_due = schedules
+      .schedule_list
+      .range((due, 0)..=(due, u64::MAX))
+      .find(|(_, stored)| stored.generation == generation)
+      .map(|(&key, _)| key);
+    at_due.or_else(|| {
+      schedules
+        .schedule_list
+        .iter()
+        .find(|(_, stored)| stored.generation == generation)
+        .map(|(&key, _)| key)
+    })
+  }
+  #[cfg(feature = "timing-wheel")]
+  {
+    let at_due = schedules
+      .wheel
+      .get_all(due)
+      .find(|(_, stored)| stored.generation == generation)
+      .map(|(sequence, _)| (due, sequence));
+    at_due.or_else(|| {
+      schedules
+        .wheel
+        .iter()
+        .find(|(_, stored)| stored.generation == generation)
+        .map(|(key, _)| key)
+    })
+  }
+}
+
+/// Set the maximum number of pending functions [try_schedule] accepts. By default the number is not limited.
+/// Functions scheduled with [schedule] are not rejected but count to the pending functions.
+pub fn set_schedule_capacity(capacity: usize) {
+  SCHEDULE_CAPACITY.store(capacity, Ordering::Release);
+}
+
+/// Add the function with the given due time to the schedules. If the current core is executing a scheduled function
+/// the function is put into the lock free intake queue that is drained as soon as the scheduled function has
+/// returned. Otherwise it is inserted with the IRQ of the current core masked, so the timer interrupt can not be
+/// raised while the lock of the schedules is held.
+fn enqueue(due: Duration, function: StoredFunction, current: Duration) {
+  if is_dispatching() {
+    push_intake(due, function);
+    return;
+  }
+
+  schedules_mut(|schedules| {
+    drain_intake(schedules);
+    insert(schedules, due, function, current);
+  });
+}
+
+/// Check whether the current core is executing a scheduled function
+fn is_dispatching() -> bool {
+  DISPATCH_DEPTH[core_id()].load(Ordering::Acquire) > 0
+}
+
+/// Call the scheduled function due at the given time and keep track that the current core is executing it
+fn call_scheduled(due: Duration, stored: StoredFunction) {
+  record_lateness(due);
+  let depth = &DISPATCH_DEPTH[core_id()];
+  depth.fetch_add(1, Ordering::AcqRel);
+  #[cfg(feature = "dispatch-hooks")]
+  let handle = stored.handle(due);
+  #[cfg(feature = "dispatch-hooks")]
+  if let Some(hook) = PRE_DISPATCH_HOOK.with_ref(|hook| *hook) {
+    hook(&handle);
+  }
+  let budget = ISR_BUDGET.load(Ordering::Acquire);
+  if budget == u64::MAX && !cfg!(feature = "dispatch-hooks") {
+    stored.function.call();
+  } else {
+    let function = stored.function;
+    let (_, elapsed) = measure(move || function.call());
+    if elapsed.as_nanos() > budget as u128 {
+      if let Some(hook) = ISR_OVERRUN_HOOK.with_ref(|hook| *hook) {
+        hook(elapsed);
+      }
+    }
+    #[cfg(feature = "dispatch-hooks")]
+    if let Some(hook) = POST_DISPATCH_HOOK.with_ref(|hook| *hook) {
+      hook(&handle, elapsed);
+    }
+  }
+  depth.fetch_sub(1, Ordering::AcqRel);
+}
+
+/// Put the function into the lock free intake queue
+fn push_intake(due: Duration, function: StoredFunction) {
+  let entry = Box::into_raw(Box::new(IntakeEntry {
+    due,
+    function,
+    next: ptr::null_mut(),
+  }));
+  let mut head = INTAKE.load(Ordering::Acquire);
+  loop {
+    // this is safe as the entry is not shared until it has been successfully pushed
+    unsafe { (*entry).next = head };
+    match INTAKE.compare_exchange_weak(head, entry, Ordering::AcqRel, Ordering::Acquire) {
+      Ok(_) => return,
+      Err(current) => head = current,
+    }
+  }
+}
+
+/// Drop all functions from the intake queue without scheduling them. This requires the mutual exclusive access to the
+/// schedules taken with [schedules_mut].
+fn discard_intake() {
+  let mut entry = INTAKE.swap(ptr::null_mut(), Ordering::AcqRel);
+  while !entry.is_null() {
+    // this is safe as the entry has been created with Box::into_raw while pushing
+    let intake = unsafe { Box::from_raw(entry) };
+    entry = intake.next;
+  }
+}
+
+/// Move all functions from the intake queue into the schedules. This requires the mutual exclusive access to the
+/// schedules taken with [schedules_mut].
+fn drain_intake(schedules: &mut Option<Schedules>) {
+  // taking the whole queue at once ensures no one else could access any of its entries
+  let mut entry = INTAKE.swap(ptr::null_mut(), Ordering::AcqRel);
+  let current = now();
+  while !entry.is_null() {
+    // this is safe as the entry has been created with Box::into_raw while pushing
+    let intake = unsafe { Box::from_raw(entry) };
+    entry = intake.next;
+    insert(schedules, intake.due, intake.function, current);
+  }
+}
+
+/// Insert the function with the given due time into the schedule list and adjust the compare value of the timer
+/// if this is the next one to be executed. The list will be created if this is the first function to be added.
+///
+/// The ``current`` time need to be the same snapshot of [now] the due time has been calculated from. Comparing
+/// against a fresh snapshot could consider the match value as still valid while the due time calculation has
+/// considered it as already passed, leaving the new function without the compare value being re-armed.
+fn insert(
+  schedules: &mut Option<Schedules>,
+  due: Duration,
+  function: StoredFunction,
+  current: Duration,
+) {
+  store(schedules, due, function);
+  if let Some(schedules) = schedules.as_ref() {
+    rearm(schedules, due, current);
+  }
+}
+
+/// Store the function with the given due time in the schedules without adjusting the compare value of the timer.
+/// The schedules will be created if this is the first function to be added. Functions due at the same time are
+/// kept apart by their sequence, so the due time is stored exactly as requested.
+fn store(schedules: &mut Option<Schedules>, due: Duration, function: StoredFunction) {
+  if schedules.is_none() {
+    // when the first function get's to be scheduled create the new sorted list
+    schedules.replace(Schedules::new());
+    #[cfg(feature = "irq-schedule")]
+    if let Some(ref schedules) = schedules {
+      // than get rid of a stale match or compare value otherwise the interrupt might be immediately triggered when
+      // activated, e.g. as the scheduler is re-initialized after it has been shut down ...
+      clear_and_arm(schedules);
+      // and activate the timer interrupts to be dispatched, a paused scheduler activates it once resumed
+      if !PAUSED.load(Ordering::Acquire) {
+        irq::activate(channel::INTERRUPT, None);
+      }
+      IRQ_ACTIVE.store(true, Ordering::Release);
+    }
+  }
+
+  let schedules = schedules.get_or_insert_with(Schedules::new);
+  let sequence = schedules.sequence;
+  schedules.sequence += 1;
+  #[cfg(not(feature = "timing-wheel"))]
+  schedules.schedule_list.insert((due, sequence), function);
+  #[cfg(feature = "timing-wheel")]
+  schedules.wheel.insert(due, sequence, function);
+  schedules.pending.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Adjust the already set match value for the interrupt to be raised after a function due at the given time has
+/// been stored. The ``current`` time need to be the snapshot the due time has been calculated from, see [insert].
+fn rearm(schedules: &Schedules, due: Duration, current: Duration) {
+  let next_due = ticks_to_duration(schedules.next_due.load(Ordering::Acquire));
+  // on first entry, when the current next due is after the new due (including the coalescing window)
+  // or when the current next_due is already in the past, set a new next due
+  if next_due.is_zero() || coalesced(due) < next_due || next_due < current {
+    arm(schedules, due);
+  };
+}
+
+/// Shut down the scheduler. All functions that are still pending will be dropped without beeing executed and the
+/// memory used to maintain the scheduled functions is released. The system timer interrupt used for the scheduling
+/// get's deactivated. It is safe to call this function even if nothing has been scheduled so far. Functions that have
+/// been scheduled from within a scheduled function but are still waiting in the intake queue are dropped as well.
+///
+/// Scheduling a new function after the shutdown will re-initialize the scheduler.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+///     schedule(Duration::from_secs(10), || println!("will never be executed"));
+///     shutdown_scheduler();
+/// # }
+/// ```
+/// A stale match or compare value does not fire the first function scheduled after the re-initialization early:
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+/// // a spy that keeps the match flag raised until it is cleared
+/// struct Spy {
+///   compare: AtomicU32,
+///   matched: AtomicBool,
+/// }
+///
+/// impl RegisterAccess for Spy {
+///   fn set_compare(&self, value: u32) {
+///     self.compare.store(value, Ordering::Release);
+///   }
+///   fn clear_match(&self) {
+///     self.matched.store(false, Ordering::Release);
+///   }
+///   fn is_matched(&self) -> bool {
+///     self.matched.load(Ordering::Acquire)
+///   }
+/// }
+///
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// static SPY: Spy = Spy { compare: AtomicU32::new(0), matched: AtomicBool::new(false) };
+/// static FIRED: AtomicBool = AtomicBool::new(false);
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// set_register_access(&SPY);
+/// schedule(Duration::from_millis(10), || ());
+/// shutdown_scheduler();
+///
+/// // the old compare value matches while the scheduler is shut down
+/// CLOCK.advance(10_000);
+/// SPY.matched.store(true, Ordering::Release);
+/// schedule(Duration::from_millis(50), || FIRED.store(true, Ordering::Release));
+/// // re-initializing has cleared the stale match and armed the new due time
+/// assert!(!SPY.matched.load(Ordering::Acquire));
+/// assert_eq!(SPY.compare.load(Ordering::Acquire), 60_000);
+/// poll_schedules();
+/// assert!(!FIRED.load(Ordering::Acquire));
+/// # }
+/// ```
+pub fn shutdown_scheduler() {
+  schedules_mut(|schedules| {
+    // taking the schedules out of the singleton drops all pending functions, the next call to schedule will
+    // create a fresh list with all indices and the next due value reset
+    discard_intake();
+    if schedules.take().is_some() {
+      #[cfg(feature = "irq-schedule")]
+      {
+        irq::deactivate(channel::INTERRUPT);
+        IRQ_ACTIVE.store(false, Ordering::Release);
+        // acknowledge a match that might have been raised in the meanwhile
+        clear_stale_match();
+      }
+    }
+  });
+}
+
+/// Pause the execution of the scheduled functions, e.g. for a critical section that reconfigures hardware shared
+/// with them. The timer interrupt is deactivated, but all pending functions are kept and new ones can still be
+/// scheduled. Polling with [poll_schedules] does not execute any function either until [resume_scheduler] is
+/// called. A function that is currently running is not interrupted.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicBool, Ordering};
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// static FIRED: AtomicBool = AtomicBool::new(false);
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// schedule(Duration::from_millis(10), || FIRED.store(true, Ordering::Release));
+/// pause_scheduler();
+/// // the function does not fire while the scheduler is paused, even though it is due
+/// CLOCK.advance(20_000);
+/// poll_schedules();
+/// assert!(!FIRED.load(Ordering::Acquire));
+/// assert_eq!(pending_schedule_count(), 1);
+///
+/// // but it is not lost and fires once the scheduler has been resumed
+/// resume_scheduler();
+/// poll_schedules();
+/// assert!(FIRED.load(Ordering::Acquire));
+/// # }
+/// ```
+pub fn pause_scheduler() {
+  // deactivating with the mutual exclusive access to the schedules keeps a concurrent activation that has not seen
+  // the pause yet from overriding it
+  #[cfg(feature = "irq-schedule")]
+  schedules_mut(|_| {
+    PAUSED.store(true, Ordering::Release);
+    if IRQ_ACTIVE.load(Ordering::Acquire) {
+      irq::deactivate(channel::INTERRUPT);
+    }
+  });
+  #[cfg(not(feature = "irq-schedule"))]
+  PAUSED.store(true, Ordering::Release);
+}
+
+/// Resume the execution of the scheduled functions paused with [pause_scheduler]. The timer is re-armed for the
+/// earliest pending function and the timer interrupt activated again, so functions that became due while the
+/// scheduler has been paused are executed right away.
+pub fn resume_scheduler() {
+  PAUSED.store(false, Ordering::Release);
+  #[cfg(feature = "irq-schedule")]
+  schedules_ref(|schedules| {
+    if let Some(ref schedules) = schedules {
+      // a match raised while paused has been acknowledged without executing any function
+      clear_and_arm(schedules);
+      if IRQ_ACTIVE.load(Ordering::Acquire) {
+        irq::activate(channel::INTERRUPT, None);
+      }
+    }
+  });
+}
+
+/// Drop all scheduled functions that have not been executed yet and return how many have been removed. In contrast
+/// to [shutdown_scheduler] the scheduler stays initialized and the timer interrupt active, so new functions can be
+/// scheduled right away. The interrupt is only deactivated while the functions are dropped if the scheduler has
+/// activated it, an interrupt of the channel activated by other means is not touched.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+///     schedule(Duration::from_millis(10), || println!("never printed"));
+///     schedule(Duration::from_millis(20), || println!("never printed"));
+///     assert_eq!(clear_all_schedules(), 2);
+///     sleep(Duration::from_millis(30));
+/// # }
+/// ```
+pub fn clear_all_schedules() -> usize {
+  schedules_mut(|schedules| {
+    // functions scheduled from within a scheduled function are pending as well
+    drain_intake(schedules);
+    let schedules = match schedules.as_mut() {
+      Some(schedules) => schedules,
+      None => return 0,
+    };
+    // keep the interrupt from being raised while the functions are dropped, an interrupt the scheduler has not
+    // activated itself is left untouched
+    #[cfg(feature = "irq-schedule")]
+    let irq_active = IRQ_ACTIVE.load(Ordering::Acquire);
+    #[cfg(feature = "irq-schedule")]
+    if irq_active {
+      irq::deactivate(channel::INTERRUPT);
+    }
+    let removed = schedules.pending.load(Ordering::Acquire);
+    // replacing the schedules drops all pending functions and resets the indices and the next due value
+    *schedules = Schedules::new();
+    // a paused scheduler is re-armed and activated once resumed
+    #[cfg(feature = "irq-schedule")]
+    if !PAUSED.load(Ordering::Acquire) {
+      // acknowledge a match that might have been raised in the meanwhile
+      clear_and_arm(schedules);
+      if irq_active {
+        irq::activate(channel::INTERRUPT, None);
+      }
+    }
+
+    removed
+  })
+}
+
+/// Get the time left until the next scheduled function is due. Returns ``None`` if there is no function pending and
+/// a zero duration if the next function is already due. This allows e.g. a power manager to decide how long the
+/// core could be halted.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+///     schedule(Duration::from_millis(200), || println!("later"));
+///     schedule(Duration::from_millis(100), || println!("sooner"));
+///     if let Some(idle) = time_until_next_schedule() {
+///       println!("next function due in {} ms", idle.as_millis());
+///     }
+/// # }
+/// ```
+pub fn time_until_next_schedule() -> Option<Duration> {
+  schedules_ref(|schedules| {
+    let schedules = schedules.as_ref()?;
+    let due = schedules.earliest()?;
+
+    Some(due.checked_sub(now()).unwrap_or_default())
+  })
+}
+
+/// Get the due times of all pending scheduled functions, the earliest first. The due times are copied while the
+/// schedules are accessed, so the returned iterator is a snapshot that does not change with functions scheduled or
+/// executed afterwards. This allows e.g. a planner to visualize the schedules or to decide how long to sleep.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// for delay in [30, 10, 20] {
+///   schedule(Duration::from_millis(delay), || ());
+/// }
+/// let upcoming: Vec<_> = upcoming_schedules().collect();
+/// assert_eq!(
+///   upcoming,
+///   [
+///     Duration::from_millis(10),
+///     Duration::from_millis(20),
+///     Duration::from_millis(30)
+///   ]
+/// );
+/// # }
+/// ```
+pub fn upcoming_schedules() -> impl Iterator<Item = Duration> {
+  schedules_ref(|schedules| {
+    schedules
+      .as_ref()
+      .map_or_else(Vec::new, Schedules::due_times)
+  })
+  .into_iter()
+}
+
+/// Get the number of scheduled functions that have not been executed yet.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+///     schedule(Duration::from_millis(100), || println!("first"));
+///     schedule(Duration::from_millis(200), || println!("second"));
+///     assert_eq!(pending_schedule_count(), 2);
+/// # }
+/// ```
+pub fn pending_schedule_count() -> usize {
+  schedules_ref(|schedules| {
+    schedules
+      .as_ref()
+      .map_or(0, |schedules| schedules.pending.load(Ordering::Acquire))
+  })
+}
+
+/// Execute all scheduled functions that are due at the time of calling this function. This allows to drive
+/// the scheduler without any interrupts from a simple main loop. In this case the functions are executed in the
+/// context of the caller and not in the context of the system timer interrupt.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+///     schedule(Duration::from_millis(100), || println!("executed from the main loop"));
+///     loop {
+///       poll_schedules();
+///       // do other stuff
+///     }
+/// # }
+/// ```
+///
+/// The functions are always taken by their due time, so a function inserted between two runs that is due before
+/// the ones already queued is executed in order:
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicUsize, Ordering};
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// // the digits of the executed functions in the order they have been executed
+/// static ORDER: AtomicUsize = AtomicUsize::new(0);
+///
+/// fn record(digit: usize) {
+///   ORDER.store(ORDER.load(Ordering::Acquire) * 10 + digit, Ordering::Release);
+/// }
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// schedule(Duration::from_millis(10), || record(1));
+/// schedule(Duration::from_millis(30), || record(3));
+/// CLOCK.advance(10_000);
+/// poll_schedules();
+/// // the new function is due before the one still queued
+/// schedule(Duration::from_millis(10), || record(2));
+/// CLOCK.advance(10_000);
+/// poll_schedules();
+/// assert_eq!(ORDER.load(Ordering::Acquire), 12);
+/// CLOCK.advance(10_000);
+/// poll_schedules();
+/// assert_eq!(ORDER.load(Ordering::Acquire), 123);
+/// assert_eq!(pending_schedule_count(), 0);
+/// # }
+/// ```
+///
+/// Polling from within a scheduled function, like a nested timer interrupt does, does not re-enter the execution of
+/// the due functions. They are executed by the outer call once the function has returned:
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicUsize, Ordering};
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// static FIRST: AtomicUsize = AtomicUsize::new(0);
+/// static SECOND: AtomicUsize = AtomicUsize::new(0);
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// schedule(Duration::from_millis(10), || {
+///   FIRST.fetch_add(1, Ordering::AcqRel);
+///   // the second function is due as well but not executed from here
+///   poll_schedules();
+///   assert_eq!(SECOND.load(Ordering::Acquire), 0);
+/// });
+/// schedule(Duration::from_millis(10), || {
+///   SECOND.fetch_add(1, Ordering::AcqRel);
+/// });
+/// CLOCK.advance(10_000);
+/// poll_schedules();
+/// assert_eq!(FIRST.load(Ordering::Acquire), 1);
+/// assert_eq!(SECOND.load(Ordering::Acquire), 1);
+/// # }
+/// ```
+pub fn poll_schedules() {
+  run_due();
+}
+
+/// Write the pending scheduled functions to the given writer to diagnose misbehaving timers, e.g. to the console.
+/// For each function its due time and the time left until it is due are written, followed by the time the timer is
+/// armed for and the compare value programmed for it. This is only available with the feature ``diagnostics``.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// schedule(Duration::from_millis(20), || ());
+/// schedule(Duration::from_millis(10), || ());
+/// schedule(Duration::from_millis(30), || ());
+/// CLOCK.advance(5_000);
+///
+/// let mut dump = String::new();
+/// dump_schedules(&mut dump).unwrap();
+/// assert_eq!(
+///   dump,
+///   "pending: 3
+///   due at 10ms in 5ms
+///   due at 20ms in 15ms
+///   due at 30ms in 25ms
+/// next due: 10ms (compare 10000)
+/// "
+/// );
+/// # }
+/// ```
+#[cfg(feature = "diagnostics")]
+pub fn dump_schedules<W: core::fmt::Write>(w: &mut W) -> core::fmt::Result {
+  let current = now();
+  let (due_times, next_due) = schedules_ref(|schedules| {
+    schedules.as_ref().map_or((Vec::new(), 0), |schedules| {
+      (
+        schedules.due_times(),
+        schedules.next_due.load(Ordering::Acquire),
+      )
+    })
+  });
+  writeln!(w, "pending: {}", due_times.len())?;
+  for due in due_times {
+    writeln!(
+      w,
+      "  due at {:?} in {:?}",
+      due,
+      due.checked_sub(current).unwrap_or_default()
+    )?;
+  }
+  if next_due == 0 {
+    writeln!(w, "next due: none")
+  } else {
+    writeln!(
+      w,
+      "next due: {:?} (compare {})",
+      ticks_to_duration(next_due),
+      next_due as u32
+    )
+  }
+}
+
+/// Get the statistics about the execution of the scheduled functions so far. The lateness of a function is the time
+/// between its due time and the moment it is actually executed. A high lateness typically indicates that functions
+/// executed before took too long, as they all run one after another in the context of the timer interrupt.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+///     // a slow function delays the one that is due right after it
+///     schedule(Duration::from_millis(1), || sleep(Duration::from_millis(5)));
+///     schedule(Duration::from_millis(2), || ());
+///     sleep(Duration::from_millis(10));
+///     let stats = schedule_stats();
+///     assert_eq!(stats.fired, 2);
+///     assert!(stats.max_lateness >= Duration::from_millis(3));
+///     assert_eq!(stats.overruns, 1);
+/// # }
+/// ```
+pub fn schedule_stats() -> ScheduleStats {
+  ScheduleStats {
+    fired: FIRED.load(Ordering::Acquire),
+    max_lateness: Duration::from_nanos(MAX_LATENESS.load(Ordering::Acquire)),
+    overruns: OVERRUNS.load(Ordering::Acquire),
+  }
+}
+
+/// Reset the statistics returned by [schedule_stats].
+pub fn reset_schedule_stats() {
+  FIRED.store(0, Ordering::Release);
+  MAX_LATENESS.store(0, Ordering::Release);
+  OVERRUNS.store(0, Ordering::Release);
+}
+
+/// Set the lateness a scheduled function need to exceed to be counted as overrun in the [ScheduleStats]. The
+/// default threshold is 1ms.
+pub fn set_overrun_threshold(threshold: Duration) {
+  OVERRUN_THRESHOLD.store(
+    threshold.as_nanos().min(u64::MAX as u128) as u64,
+    Ordering::Release,
+  );
+}
+
+/// Check whether the interrupt used to execute the scheduled functions has been activated by this crate. This is
+/// only possible with the feature ``irq-schedule`` active. Whether the interrupts are globally enabled can not be
+/// detected here, this is in the responsibility of the program.
+pub fn schedule_irq_ready() -> bool {
+  cfg!(feature = "irq-schedule") && IRQ_ACTIVE.load(Ordering::Acquire)
+}
+
+/// Execute all scheduled functions that became due before the interrupts have been set up and make sure the
+/// remaining ones will be executed by the timer interrupt.
+///
+/// Functions can be scheduled at any time, even before the interrupts are initialized and globally enabled. In this
+/// case they are just queued. However, the initialization of the interrupts might deactivate the already activated
+/// timer interrupt again and functions might become due while the interrupts are still disabled. The recommended
+/// init ordering therefore is:
+/// 1. initialize the interrupts with ``ruspiro_interrupt::initialize()``
+/// 2. globally enable the interrupts with ``ruspiro_interrupt::enable_interrupts()``
+/// 3. call [flush_due_schedules] once if functions might have been scheduled before step 1.
+///
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # fn doc() {
+///     // scheduling before the interrupts are set up just queues the function
+///     schedule(Duration::from_millis(1), || println!("early bird"));
+///     // ... initialize and enable interrupts ...
+///     flush_due_schedules();
+/// # }
+/// ```
+pub fn flush_due_schedules() {
+  poll_schedules();
+  #[cfg(feature = "irq-schedule")]
+  schedules_ref(|schedules| {
+    if let Some(ref schedules) = schedules {
+      // re-arm the compare value for the next pending function as the one set might already be in the past, a
+      // paused scheduler does this once resumed
+      if !PAUSED.load(Ordering::Acquire) {
+        clear_and_arm(schedules);
+        irq::activate(channel::INTERRUPT, None);
+      }
+      IRQ_ACTIVE.store(true, Ordering::Release);
+    }
+  });
+}
+
+/// Arm the compare value for the function due at the given time. The interrupt is delayed by the coalescing window,
+/// so all functions that become due until then are executed with the same interrupt.
+///
+/// The channel only matches if the counter equals the compare value. A value that has already passed, e.g. because a
+/// very short delay elapsed while the function was inserted, would only match once the lower 32Bit of the counter
+/// wrapped around after more than an hour. So the compare value is set at least [MIN_SCHEDULE_MARGIN] ahead of the
+/// counter and re-armed if the counter has passed it while it was written.
+fn arm(schedules: &Schedules, due: Duration) {
+  let margin = duration_to_ticks(MIN_SCHEDULE_MARGIN);
+  let mut ticks = duration_to_ticks(coalesced(due));
+  loop {
+    ticks = ticks.max(duration_to_ticks(now()).saturating_add(margin));
+    schedules.next_due.store(ticks, Ordering::Release);
+    set_compare(ticks as u32);
+    if duration_to_ticks(now()) < ticks {
+      return;
+    }
+  }
+}
+
+/// Clear a stale match of the scheduler channel and arm the compare value for the earliest pending function. This is
+/// done on every path that (re)activates the timer interrupt, as a match raised while the interrupt has been inactive
+/// or a compare value left over from before the scheduler has been shut down would otherwise raise the interrupt
+/// immediately. If no function is pending the compare value stays parked, see [clear_stale_match].
+#[cfg(feature = "irq-schedule")]
+fn clear_and_arm(schedules: &Schedules) {
+  clear_stale_match();
+  match schedules.earliest() {
+    Some(due) => arm(schedules, due),
+    // let the next function scheduled arm the compare value
+    None => schedules.next_due.store(0, Ordering::Release),
+  }
+}
+
+/// Park the compare value of the scheduler channel right behind the counter and clear its match flag afterwards. A
+/// parked compare value only matches once the lower 32Bit of the counter wrapped around, so no match can be raised
+/// between clearing the flag and arming the next compare value.
+#[cfg(feature = "irq-schedule")]
+fn clear_stale_match() {
+  set_compare((duration_to_ticks(now()) as u32).wrapping_sub(1));
+  clear_match();
+}
+
+/// The time the interrupt for a function due at the given time is raised, including the coalescing window
+fn coalesced(due: Duration) -> Duration {
+  due.saturating_add(Duration::from_nanos(
+    COALESCE_WINDOW.load(Ordering::Acquire),
+  ))
+}
+
+/// Execute all scheduled functions that are due unless the current core is already executing them, e.g. as this is
+/// a nested timer interrupt raised while a long running function is executed. In this case the core executing the
+/// functions is told to re-evaluate the schedules once the current function has returned.
+fn run_due() {
+  let core = core_id();
+  loop {
+    if RUNNING_DUE[core].swap(true, Ordering::AcqRel) {
+      RERUN_DUE[core].store(true, Ordering::Release);
+      return;
+    }
+    execute_due();
+    RUNNING_DUE[core].store(false, Ordering::Release);
+    // a nested interrupt raised after the last check for due functions has left them to this one
+    if !RERUN_DUE[core].swap(false, Ordering::AcqRel) {
+      return;
+    }
+  }
+}
+
+/// Execute all scheduled functions that are due, the earliest one first. Each function is taken out of the schedules
+/// and the match value for the next one is set with mutual exclusive access to the schedules, but the function itself
+/// is called after the access has been released.
+///
+/// All bookkeeping is done before the function is actually called. If the function never returns, e.g. because it
+/// panics on a target using ``panic=abort``, it has already been removed and the match value advanced, so the same
+/// function will never be executed twice. Unwinding a panic is not supported on the bare metal targets, so a
+/// panicking function still brings down the core it is executed on.
+fn execute_due() {
+  loop {
+    // a function executed before might have paused the scheduler
+    if PAUSED.load(Ordering::Acquire) {
+      return;
+    }
+    let next = schedules_mut(|schedules| {
+      drain_intake(schedules);
+      let schedules = schedules.as_mut()?;
+      let (due, stored) = schedules.pop_due()?;
+      schedules.pending.fetch_sub(1, Ordering::AcqRel);
+      // setup the match value for the next function to be triggered, if it's already due it will be taken with the
+      // next iteration of this loop
+      if let Some(next_due) = schedules.earliest() {
+        arm(schedules, next_due);
+      }
+
+      Some((due, stored))
+    });
+
+    match next {
+      Some((due, stored)) => call_scheduled(due, stored),
+      None => return,
+    }
+  }
+}
+
+/// Set the window the interrupt for a due function may be delayed, so functions that become due within this window
+/// are executed with a single interrupt instead of raising one interrupt for each of them. This reduces the
+/// interrupt load if many functions are scheduled close to each other. The trade-off is an additional latency of up
+/// to the window for each function. The default window is zero, so each function is executed as soon as it is due.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicUsize, Ordering};
+/// static FIRED: AtomicUsize = AtomicUsize::new(0);
+///
+/// # fn doc() {
+/// set_coalesce_window(Duration::from_micros(50));
+/// // 5 functions due within 40µs are executed with the interrupt raised 50µs after the first one is due
+/// for i in 0..5 {
+///   schedule(Duration::from_micros(1_000 + i * 10), || {
+///     FIRED.fetch_add(1, Ordering::AcqRel);
+///   });
+/// }
+/// sleep(Duration::from_micros(1_045));
+/// assert_eq!(FIRED.load(Ordering::Acquire), 0);
+/// sleep(Duration::from_micros(10));
+/// assert_eq!(FIRED.load(Ordering::Acquire), 5);
+/// # }
+/// ```
+pub fn set_coalesce_window(window: Duration) {
+  COALESCE_WINDOW.store(
+    window.as_nanos().min(u64::MAX as u128) as u64,
+    Ordering::Release,
+  );
+}
+
+/// Set the time a single scheduled function may take to execute. As the scheduled functions run in the context of
+/// the timer interrupt one after another, a function taking longer delays all other functions and interrupts. If a
+/// function exceeds this budget the hook set with [on_isr_overrun] is called with the time the function took. There
+/// is no budget by default, ``Duration::MAX`` removes it again.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// fn report(elapsed: Duration) {
+///   println!("scheduled function took {:?}", elapsed);
+/// }
+///
+/// # fn doc() {
+/// set_isr_budget(Duration::from_micros(50));
+/// on_isr_overrun(report);
+/// // this deliberately slow function triggers the hook
+/// schedule(Duration::from_millis(1), || sleep(Duration::from_millis(1)));
+/// # }
+/// ```
+pub fn set_isr_budget(budget: Duration) {
+  ISR_BUDGET.store(
+    budget.as_nanos().min(u64::MAX as u128) as u64,
+    Ordering::Release,
+  );
+}
+
+/// Set the hook that is called with the execution time of a scheduled function that exceeded the budget set with
+/// [set_isr_budget]. The hook is called from the context of the timer interrupt as well, so it should only record
+/// the overrun.
+pub fn on_isr_overrun(hook: fn(Duration)) {
+  irq_masked(|| ISR_OVERRUN_HOOK.with_mut(|current| *current = Some(hook)));
+}
+
+/// Set the hook that is called right before each scheduled function is executed, e.g. to integrate a tracing
+/// subsystem without modifying each function. The hook gets a [ScheduleHandle] equal to the one returned when the
+/// function has been scheduled. It is called from the context of the timer interrupt like the function itself.
+/// This is only available with the feature ``dispatch-hooks``.
+/// # Example
+/// ```no_run
+/// # use ruspiro_timer::*;
+/// # use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+/// static CLOCK: MockTimeSource = MockTimeSource::new(0);
+/// static STEP: AtomicUsize = AtomicUsize::new(0);
+/// static TOOK: AtomicU64 = AtomicU64::new(0);
+///
+/// fn before(_: &ScheduleHandle) {
+///   assert_eq!(STEP.fetch_add(1, Ordering::AcqRel), 0);
+/// }
+///
+/// fn after(_: &ScheduleHandle, elapsed: Duration) {
+///   assert_eq!(STEP.fetch_add(1, Ordering::AcqRel), 2);
+///   TOOK.store(elapsed.as_micros() as u64, Ordering::Release);
+/// }
+///
+/// # fn doc() {
+/// set_time_source(&CLOCK);
+/// set_pre_dispatch(before);
+/// set_post_dispatch(after);
+/// schedule(Duration::from_millis(10), || {
+///   assert_eq!(STEP.fetch_add(1, Ordering::AcqRel), 1);
+///   // the function takes 5ms
+///   CLOCK.advance(5_000);
+/// });
+/// CLOCK.advance(10_000);
+/// poll_schedules();
+/// assert_eq!(STEP.load(Ordering::Acquire), 3);
+/// assert_eq!(TOOK.load(Ordering::Acquire), 5_000);
+/// # }
+/// ```
+#[cfg(feature = "dispatch-hooks")]
+pub fn set_pre_dispatch(hook: fn(&ScheduleHandle)) {
+  irq_masked(|| PRE_DISPATCH_HOOK.with_mut(|current| *current = Some(hook)));
+}
+
+/// Set the hook that is called right after each scheduled function has been executed with the time it took, see
+/// [set_pre_dispatch]. This is only available with the feature ``dispatch-hooks``.
+#[cfg(feature = "dispatch-hooks")]
+pub fn set_post_dispatch(hook: fn(&ScheduleHandle, Duration)) {
+  irq_masked(|| POST_DISPATCH_HOOK.with_mut(|current| *current = Some(hook)));
+}
+
+/// Update the statistics with the lateness of a function due at the given time that is about to be executed
+fn record_lateness(due: Duration) {
+  let actual = now();
+  latency::record_sample(due, actual);
+  let lateness = actual.checked_sub(due).unwrap_or_default();
+  let lateness = lateness.as_nanos().min(u64::MAX as u128) as u64;
+  FIRED.fetch_add(1, Ordering::AcqRel);
+  MAX_LATENESS.fetch_max(lateness, Ordering::AcqRel);
+  if lateness > OVERRUN_THRESHOLD.load(Ordering::Acquire) {
+    OVERRUNS.fetch_add(1, Ordering::AcqRel);
+  }
+}
+
+/// Implement the timer interrupt handler for interrupt based timed execution
+#[cfg(all(feature = "irq-schedule", not(feature = "schedule-timer3")))]
+#[IrqHandler(SystemTimer1)]
+unsafe fn timer_handler(channel: Option<IsrSender<Box<dyn Any>>>) {
+  handle_timer_irq();
+}
+
+/// Implement the timer interrupt handler for interrupt based timed execution
+#[cfg(all(feature = "irq-schedule", feature = "schedule-timer3"))]
+#[IrqHandler(SystemTimer3)]
+unsafe fn timer_handler(channel: Option<IsrSender<Box<dyn Any>>>) {
+  handle_timer_irq();
+}
+
+/// Execute the next scheduled function once the compare value of the scheduler channel has matched
+#[cfg(feature = "irq-schedule")]
+unsafe fn handle_timer_irq() {
+  // check which timer compare/match value has raised this interrupt, only care on the scheduler channel ...
+  // the match flags of the other channels are left untouched as they belong to different owners and are
+  // signaled with different interrupt lines
+  if is_matched() {
+    // first acknowledge the timer interrupt by writing 1 to the match register value of our channel only, this
+    // ensures the interrupt line is never left asserted even if there is nothing to execute
+    clear_match();
+    // execute all functions that are due by now, further functions than the one that raised this match might be due
+    // already, e.g. as their interrupt has been coalesced with this one
+    run_due();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{setup, CLOCK, SPY};
+  use core::sync::atomic::AtomicUsize;
+  use std::{sync::Mutex, thread, vec::Vec};
+
+  /// A counter shared with the scheduled functions
+  fn counter() -> (Arc<AtomicUsize>, Arc<AtomicUsize>) {
+    let count = Arc::new(AtomicUsize::new(0));
+    (Arc::clone(&count), count)
+  }
+
+  #[test]
+  fn poll_executes_function_once_due() {
+    let _guard = setup();
+    let (fired, count) = counter();
+    schedule(Duration::from_millis(10), move || {
+      count.fetch_add(1, Ordering::AcqRel);
+    });
+
+    CLOCK.advance(9_999);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 0);
+    assert_eq!(pending_schedule_count(), 1);
+
+    CLOCK.advance(1);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 1);
+    assert_eq!(pending_schedule_count(), 0);
+
+    // polling again does not execute the function a second time
+    CLOCK.advance(10_000);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 1);
+  }
+
+  #[test]
+  fn poll_executes_due_functions_in_order() {
+    let _guard = setup();
+    let order = Arc::new(Mutex::new(Vec::new()));
+    for (delay, id) in [(30, 3), (10, 1), (20, 2), (40, 4)] {
+      let order = Arc::clone(&order);
+      schedule(Duration::from_millis(delay), move || {
+        order.lock().unwrap().push(id)
+      });
+    }
+
+    CLOCK.advance(30_000);
+    poll_schedules();
+    assert_eq!(*order.lock().unwrap(), [1, 2, 3]);
+    assert_eq!(pending_schedule_count(), 1);
+
+    CLOCK.advance(10_000);
+    poll_schedules();
+    assert_eq!(*order.lock().unwrap(), [1, 2, 3, 4]);
+  }
+
+  #[test]
+  fn past_due_function_fires_on_next_poll() {
+    let _guard = setup();
+    CLOCK.set(10_000);
+    let (fired, count) = counter();
+    schedule_at(Duration::from_millis(5), move || {
+      count.fetch_add(1, Ordering::AcqRel);
+    });
+    // the compare value can not be set to the past, but the function is due nevertheless
+    assert_eq!(SPY.compare.load(Ordering::Acquire), 10_002);
+
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 1);
+    assert_eq!(pending_schedule_count(), 0);
+  }
+
+  #[test]
+  fn delay_below_margin_is_not_extended() {
+    let _guard = setup();
+    let (fired, count) = counter();
+    schedule(Duration::from_nanos(100), move || {
+      count.fetch_add(1, Ordering::AcqRel);
+    });
+    assert_eq!(SPY.compare.load(Ordering::Acquire), 2);
+
+    // the function is due after its delay, before the compare value set with the margin is reached
+    CLOCK.advance(1);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 1);
+  }
+
+  #[test]
+  fn function_mutating_shared_state_does_not_block_the_next_one() {
+    let _guard = setup();
+    let state = Arc::new(Mutex::new(Vec::new()));
+    let (fired, count) = counter();
+    {
+      let state = Arc::clone(&state);
+      schedule(Duration::from_millis(10), move || {
+        state.lock().unwrap().push(1);
+      });
+    }
+    schedule(Duration::from_millis(20), move || {
+      count.fetch_add(1, Ordering::AcqRel);
+    });
+
+    CLOCK.advance(10_000);
+    poll_schedules();
+    assert_eq!(*state.lock().unwrap(), [1]);
+    assert_eq!(fired.load(Ordering::Acquire), 0);
+    assert_eq!(SPY.compare.load(Ordering::Acquire), 20_000);
+
+    CLOCK.advance(10_000);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 1);
+    assert_eq!(pending_schedule_count(), 0);
+  }
+
+  #[test]
+  fn poll_executes_function_scheduled_from_scheduled_function() {
+    let _guard = setup();
+    let (fired, count) = counter();
+    schedule(Duration::from_millis(10), move || {
+      count.fetch_add(1, Ordering::AcqRel);
+      schedule(Duration::from_millis(10), move || {
+        count.fetch_add(1, Ordering::AcqRel);
+      });
+    });
+
+    CLOCK.advance(10_000);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 1);
+    assert_eq!(pending_schedule_count(), 1);
+
+    CLOCK.advance(10_000);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 2);
+    assert_eq!(pending_schedule_count(), 0);
+  }
+
+  #[test]
+  fn poll_arms_compare_for_next_function() {
+    let _guard = setup();
+    schedule(Duration::from_millis(10), || ());
+    schedule(Duration::from_millis(20), || ());
+    assert_eq!(SPY.compare.load(Ordering::Acquire), 10_000);
+
+    CLOCK.advance(10_000);
+    poll_schedules();
+    assert_eq!(SPY.compare.load(Ordering::Acquire), 20_000);
+  }
+
+  #[test]
+  fn earlier_function_rearms_compare() {
+    let _guard = setup();
+    schedule(Duration::from_millis(20), || ());
+    assert_eq!(SPY.compare.load(Ordering::Acquire), 20_000);
+
+    schedule(Duration::from_millis(10), || ());
+    assert_eq!(SPY.compare.load(Ordering::Acquire), 10_000);
+  }
+
+  #[test]
+  fn paused_scheduler_executes_nothing_until_resumed() {
+    let _guard = setup();
+    let (fired, count) = counter();
+    schedule(Duration::from_millis(10), move || {
+      count.fetch_add(1, Ordering::AcqRel);
+    });
+
+    pause_scheduler();
+    CLOCK.advance(10_000);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 0);
+
+    resume_scheduler();
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 1);
+  }
+
+  #[test]
+  fn dropped_guard_cancels_function() {
+    let _guard = setup();
+    let (fired, count) = counter();
+    let scoped = schedule_scoped(Duration::from_millis(10), move || {
+      count.fetch_add(1, Ordering::AcqRel);
+    });
+    assert_eq!(pending_schedule_count(), 1);
+
+    drop(scoped);
+    assert_eq!(pending_schedule_count(), 0);
+    CLOCK.advance(10_000);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 0);
+  }
+
+  #[test]
+  fn detached_guard_keeps_function() {
+    let _guard = setup();
+    let (fired, count) = counter();
+    let handle = schedule_scoped(Duration::from_millis(10), move || {
+      count.fetch_add(1, Ordering::AcqRel);
+    })
+    .detach();
+    assert!(handle.is_pending());
+
+    CLOCK.advance(10_000);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 1);
+    assert!(handle.has_fired());
+  }
+
+  #[test]
+  fn guard_dropped_after_fire_has_no_effect() {
+    let _guard = setup();
+    let (fired, count) = counter();
+    let scoped = {
+      let count = Arc::clone(&count);
+      schedule_scoped(Duration::from_millis(10), move || {
+        count.fetch_add(1, Ordering::AcqRel);
+      })
+    };
+    CLOCK.advance(10_000);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 1);
+
+    // a newer function due at the same time must not be cancelled by the outdated guard
+    schedule_at(Duration::from_millis(10), move || {
+      count.fetch_add(1, Ordering::AcqRel);
+    });
+    drop(scoped);
+    assert_eq!(pending_schedule_count(), 1);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 2);
+  }
+
+  #[test]
+  fn concurrent_polls_execute_each_function_exactly_once() {
+    const THREADS: usize = 4;
+    const FUNCTIONS: usize = 500;
+    let _guard = setup();
+    let runs: Arc<Vec<AtomicUsize>> = Arc::new(
+      (0..THREADS * FUNCTIONS * 2)
+        .map(|_| AtomicUsize::new(0))
+        .collect(),
+    );
+
+    // schedule the functions from all threads while they poll, only the ones without delay are due already
+    let spawn_all = |work: fn(usize, &Arc<Vec<AtomicUsize>>)| {
+      let threads: Vec<_> = (0..THREADS)
+        .map(|thread| {
+          let runs = Arc::clone(&runs);
+          thread::spawn(move || work(thread, &runs))
+        })
+        .collect();
+      for thread in threads {
+        thread.join().unwrap();
+      }
+    };
+    spawn_all(|thread, runs| {
+      for i in 0..FUNCTIONS {
+        let id = thread * FUNCTIONS + i;
+        let runs = Arc::clone(runs);
+        schedule(Duration::from_micros((id % 100) as u64), move || {
+          runs[id].fetch_add(1, Ordering::AcqRel);
+          // the first half of the functions of each thread schedules a follow-up from within the scheduled function
+          if i < FUNCTIONS / 2 {
+            let runs = Arc::clone(&runs);
+            schedule(Duration::from_nanos(0), move || {
+              runs[THREADS * FUNCTIONS + id].fetch_add(1, Ordering::AcqRel);
+            });
+          }
+        });
+        poll_schedules();
+      }
+    });
+
+    // the time does not advance while polling concurrently as each thread would read the shared mock clock
+    CLOCK.set(100);
+    spawn_all(|_, _| {
+      for _ in 0..FUNCTIONS {
+        poll_schedules();
+        thread::yield_now();
+      }
+    });
+    poll_schedules();
+
+    assert_eq!(pending_schedule_count(), 0);
+    for (id, runs) in runs.iter().enumerate() {
+      let expected = match id.checked_sub(THREADS * FUNCTIONS) {
+        Some(follow_up) if follow_up % FUNCTIONS >= FUNCTIONS / 2 => 0,
+        _ => 1,
+      };
+      assert_eq!(runs.load(Ordering::Acquire), expected, "function {}", id);
+    }
+  }
+
+  #[test]
+  fn reschedule_moves_function_earlier() {
+    let _guard = setup();
+    let (fired, count) = counter();
+    let mut handle = schedule_at(Duration::from_millis(30), move || {
+      count.fetch_add(1, Ordering::AcqRel);
+    });
+
+    assert!(reschedule(&mut handle, Duration::from_millis(10)));
+    assert_eq!(SPY.compare.load(Ordering::Acquire), 10_000);
+    CLOCK.advance(10_000);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 1);
+    assert!(handle.has_fired());
+    assert_eq!(pending_schedule_count(), 0);
+  }
+
+  #[test]
+  fn reschedule_moves_function_later() {
+    let _guard = setup();
+    let (fired, count) = counter();
+    let mut handle = schedule_at(Duration::from_millis(10), move || {
+      count.fetch_add(1, Ordering::AcqRel);
+    });
+
+    CLOCK.advance(5_000);
+    assert!(reschedule(&mut handle, Duration::from_millis(10)));
+    CLOCK.advance(5_000);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 0);
+    assert!(handle.is_pending());
+
+    CLOCK.advance(5_000);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 1);
+    assert_eq!(pending_schedule_count(), 0);
+  }
+
+  #[test]
+  fn reschedule_of_fired_function_fails() {
+    let _guard = setup();
+    let (fired, count) = counter();
+    let mut handle = schedule_at(Duration::from_millis(10), move || {
+      count.fetch_add(1, Ordering::AcqRel);
+    });
+    CLOCK.advance(10_000);
+    poll_schedules();
+
+    assert!(!reschedule(&mut handle, Duration::from_millis(10)));
+    assert_eq!(pending_schedule_count(), 0);
+    CLOCK.advance(10_000);
+    poll_schedules();
+    assert_eq!(fired.load(Ordering::Acquire), 1);
   }
 }
@@ -0,0 +1,162 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Unit Test Support
+//!
+//! The unit tests run on an aarch64 host in user mode, where neither the timer peripherals nor the interrupts can be
+//! accessed. So the clock is replaced with the [CLOCK], or the [TICKING] clock for functions waiting for the time to
+//! pass, and the compare channel of the scheduler with the [SPY] recording the accesses to it. The timer register themselves are mocked with the plain memory of [REGISTERS] the
+//! peripheral base address points to. All of them are global like the schedules, so each test using them holds the
+//! [TestGuard] returned by [setup] to run one after the other.
+//!
+
+use crate::*;
+#[cfg(feature = "schedule")]
+use core::sync::atomic::{AtomicU32, AtomicUsize};
+use core::{
+  cell::UnsafeCell,
+  ptr,
+  sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use std::thread;
+
+/// The number of 32Bit words of the timer register, from the system timer up to the last ARM timer register
+const REGISTER_WORDS: usize = (0xB000 + 0x424) / 4;
+
+/// Plain memory mocking the timer register
+pub(crate) struct MockRegisters(UnsafeCell<[u32; REGISTER_WORDS]>);
+
+// the memory is only accessed with volatile reads and writes of single words like the real register
+unsafe impl Sync for MockRegisters {}
+
+impl MockRegisters {
+  /// The address the peripheral base need to be set to for the register to be located in this memory
+  pub(crate) fn base(&self) -> usize {
+    self.0.get() as usize
+  }
+
+  /// Read the register at the given offset from the peripheral base address
+  pub(crate) fn read(&self, offset: usize) -> u32 {
+    // this is safe as the offset is checked against the size of the memory
+    unsafe { ptr::read_volatile(&(*self.0.get())[offset / 4]) }
+  }
+
+  /// Write the register at the given offset from the peripheral base address, e.g. to provide the counter value of a
+  /// read-only register
+  pub(crate) fn write(&self, offset: usize, value: u32) {
+    // this is safe as the offset is checked against the size of the memory
+    unsafe { ptr::write_volatile(&mut (*self.0.get())[offset / 4], value) };
+  }
+
+  /// Reset all register to 0
+  fn clear(&self) {
+    // this is safe as the memory is only accessed by the test holding the guard
+    unsafe { ptr::write_bytes(self.0.get(), 0, 1) };
+  }
+}
+
+/// The timer register used by all unit tests
+pub(crate) static REGISTERS: MockRegisters = MockRegisters(UnsafeCell::new([0; REGISTER_WORDS]));
+
+/// The clock all unit tests are based on
+pub(crate) static CLOCK: MockTimeSource = MockTimeSource::new(0);
+
+/// A clock advancing by one tick with each read, so functions waiting for the time to pass return without another
+/// thread advancing the [CLOCK]
+pub(crate) struct TickingClock(AtomicU64);
+
+impl TimeSource for TickingClock {
+  fn ticks(&self) -> u64 {
+    self.0.fetch_add(1, Ordering::AcqRel)
+  }
+}
+
+/// The ticking clock used by the unit tests, it is selected with ``set_time_source(&TICKING)`` after [setup]
+pub(crate) static TICKING: TickingClock = TickingClock(AtomicU64::new(0));
+
+/// The [RegisterAccess] replacing the compare channel of the scheduler in the unit tests
+#[cfg(feature = "schedule")]
+pub(crate) struct Spy {
+  /// The compare value set last
+  pub(crate) compare: AtomicU32,
+  /// The number of times the compare value has been set
+  pub(crate) armed: AtomicUsize,
+  /// The match flag of the channel, to be raised by the test
+  pub(crate) matched: AtomicBool,
+}
+
+#[cfg(feature = "schedule")]
+impl RegisterAccess for Spy {
+  fn set_compare(&self, value: u32) {
+    self.compare.store(value, Ordering::Release);
+    self.armed.fetch_add(1, Ordering::AcqRel);
+  }
+
+  fn clear_match(&self) {
+    self.matched.store(false, Ordering::Release);
+  }
+
+  fn is_matched(&self) -> bool {
+    self.matched.load(Ordering::Acquire)
+  }
+}
+
+/// The compare channel of the scheduler used by all unit tests
+#[cfg(feature = "schedule")]
+pub(crate) static SPY: Spy = Spy {
+  compare: AtomicU32::new(0),
+  armed: AtomicUsize::new(0),
+  matched: AtomicBool::new(false),
+};
+
+/// Flag set while a test holds the [TestGuard]
+static SERIAL: AtomicBool = AtomicBool::new(false);
+
+/// Guard of a unit test using the global state of this crate, the next test starts once it is dropped. This also
+/// happens if the test panics.
+pub(crate) struct TestGuard;
+
+impl Drop for TestGuard {
+  fn drop(&mut self) {
+    SERIAL.store(false, Ordering::Release);
+  }
+}
+
+/// Wait until no other test uses the global state and reset it. The [CLOCK] and [TICKING] start over at 0 ticks, which
+/// is the zero point of the uptime as well, the per-core lists are emptied and the scheduler is shut down with its
+/// settings reset to their defaults.
+pub(crate) fn setup() -> TestGuard {
+  while SERIAL
+    .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+    .is_err()
+  {
+    thread::yield_now();
+  }
+
+  REGISTERS.clear();
+  interface::use_mock_register(REGISTERS.base());
+  CLOCK.set(0);
+  TICKING.0.store(0, Ordering::Release);
+  core_schedule::clear_core_schedules();
+  // setting the time source again restarts the check for the time going backwards
+  set_time_source(&CLOCK);
+  reset_epoch();
+  #[cfg(feature = "schedule")]
+  {
+    set_register_access(&SPY);
+    shutdown_scheduler();
+    resume_scheduler();
+    set_schedule_capacity(usize::MAX);
+    set_coalesce_window(Duration::from_nanos(0));
+    reset_schedule_stats();
+    SPY.compare.store(0, Ordering::Release);
+    SPY.armed.store(0, Ordering::Release);
+    SPY.matched.store(false, Ordering::Release);
+  }
+
+  TestGuard
+}
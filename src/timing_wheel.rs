@@ -0,0 +1,268 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Timing Wheel
+//!
+//! Hashed timing wheel used as the backend of the scheduler with the feature ``timing-wheel`` active. The entries
+//! are hashed by their due time into a fixed number of slots each covering ~1ms. Inserting an entry is O(1) as it is
+//! just pushed to its slot. Expiring entries only visits the slots that have passed since the last expiry and the
+//! entries within them. This scales much better than the sorted list for programs with thousands of short lived
+//! timers, at the cost of scanning a slot when searching the earliest entry within it.
+//!
+//! Entries due more than one revolution of the wheel (~262ms) ahead share the slot with nearer ones and are skipped
+//! until the wheel has turned far enough. Several entries may be due at the same time, they are told apart by the
+//! sequence number given with each of them and expire in the order of it.
+//!
+
+use crate::duration_to_ticks;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// Number of slots of the wheel, need to be a power of 2
+const SLOTS: usize = 256;
+
+/// The time covered by each slot in ticks of the system timer
+const SLOT_TICKS: u64 = 1024;
+
+/// An entry stored in a slot of the wheel
+struct Entry<T> {
+  due: Duration,
+  sequence: u64,
+  value: T,
+}
+
+impl<T> Entry<T> {
+  /// The key identifying the entry, entries due at the same time are ordered by their sequence
+  fn key(&self) -> (Duration, u64) {
+    (self.due, self.sequence)
+  }
+}
+
+/// The hashed timing wheel storing values with the time they are due
+pub(crate) struct TimingWheel<T> {
+  slots: Vec<Vec<Entry<T>>>,
+  /// The absolute number of the slot that will be visited next, all entries of earlier slots have been taken already
+  cursor: u64,
+  /// The number of entries stored in the wheel
+  len: usize,
+}
+
+impl<T> TimingWheel<T> {
+  /// Create a new empty wheel starting to turn at the given time
+  pub fn new(now: Duration) -> Self {
+    let mut slots = Vec::with_capacity(SLOTS);
+    slots.resize_with(SLOTS, Vec::new);
+    Self {
+      slots,
+      cursor: slot_of(now),
+      len: 0,
+    }
+  }
+
+  /// The number of entries stored in the wheel
+  #[allow(dead_code)]
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  /// Insert a value due at the given time with the given sequence number, that need to be unique among the entries
+  /// due at the same time. A due time that belongs to a slot already passed is put into the slot to be visited next,
+  /// so it is taken with the next expiry.
+  pub fn insert(&mut self, due: Duration, sequence: u64, value: T) {
+    let slot = slot_of(due).max(self.cursor);
+    self.slots[index(slot)].push(Entry {
+      due,
+      sequence,
+      value,
+    });
+    self.len += 1;
+  }
+
+  /// Get the sequence numbers and values of all entries due at exactly the given time, in no particular order
+  pub fn get_all(&self, due: Duration) -> impl Iterator<Item = (u64, &T)> {
+    self
+      .slot_for(due)
+      .iter()
+      .chain(self.slots[index(self.cursor)].iter())
+      .filter(move |entry| entry.due == due)
+      .map(|entry| (entry.sequence, &entry.value))
+  }
+
+  /// Remove the value due at exactly the given time with the given sequence number from the wheel
+  pub fn remove(&mut self, due: Duration, sequence: u64) -> Option<T> {
+    for slot in [index(slot_of(due)), index(self.cursor)] {
+      if let Some(pos) = self.slots[slot]
+        .iter()
+        .position(|entry| entry.key() == (due, sequence))
+      {
+        self.len -= 1;
+        return Some(self.slots[slot].swap_remove(pos).value);
+      }
+    }
+
+    None
+  }
+
+  /// Take the earliest value out of the wheel that is due at the given time. Returns ``None`` if no value is due.
+  pub fn pop_due(&mut self, now: Duration) -> Option<(Duration, T)> {
+    if self.len == 0 {
+      // nothing to expire, just catch up with the time
+      self.cursor = self.cursor.max(slot_of(now));
+      return None;
+    }
+
+    let target = slot_of(now);
+    // after a long pause each slot need to be visited only once to find all due entries
+    if target > self.cursor + SLOTS as u64 {
+      self.cursor = target - SLOTS as u64;
+    }
+    loop {
+      let slot = &mut self.slots[index(self.cursor)];
+      let earliest = slot
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.due <= now)
+        .min_by_key(|(_, entry)| entry.key())
+        .map(|(pos, _)| pos);
+      if let Some(pos) = earliest {
+        self.len -= 1;
+        let entry = slot.swap_remove(pos);
+        return Some((entry.due, entry.value));
+      }
+      if self.cursor >= target {
+        return None;
+      }
+      self.cursor += 1;
+    }
+  }
+
+  /// Get the due times of all values stored in the wheel, in no particular order
+  pub fn due_times(&self) -> impl Iterator<Item = Duration> + '_ {
+    self.slots.iter().flatten().map(|entry| entry.due)
+  }
+
+  /// Get the due time of the earliest value stored in the wheel
+  pub fn next_due(&self) -> Option<Duration> {
+    if self.len == 0 {
+      return None;
+    }
+    // walk the slots of the current revolution and only consider entries belonging to it
+    for slot in self.cursor..self.cursor + SLOTS as u64 {
+      let earliest = self.slots[index(slot)]
+        .iter()
+        .filter(|entry| slot_of(entry.due) <= slot)
+        .map(|entry| entry.due)
+        .min();
+      if earliest.is_some() {
+        return earliest;
+      }
+    }
+    // all entries are due more than one revolution ahead
+    self.iter().map(|((due, _), _)| due).min()
+  }
+
+  /// Iterate over all entries of the wheel with their due time and sequence number in no particular order
+  pub fn iter(&self) -> impl Iterator<Item = ((Duration, u64), &T)> {
+    self
+      .slots
+      .iter()
+      .flat_map(|slot| slot.iter().map(|entry| (entry.key(), &entry.value)))
+  }
+
+  /// Slot the given due time is stored in if it has not passed already
+  fn slot_for(&self, due: Duration) -> &Vec<Entry<T>> {
+    &self.slots[index(slot_of(due))]
+  }
+}
+
+/// The absolute number of the slot the given time belongs to
+fn slot_of(time: Duration) -> u64 {
+  duration_to_ticks(time) / SLOT_TICKS
+}
+
+/// The index into the slots of the wheel for the given absolute slot number
+fn index(slot: u64) -> usize {
+  slot as usize & (SLOTS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::setup;
+  use alloc::collections::BTreeMap;
+
+  /// The number of timers inserted by the stress test and the benchmark
+  const TIMERS: u64 = 10_000;
+
+  /// Pseudo random due times in ticks spread over about 4 revolutions of the wheel
+  fn due_ticks() -> impl Iterator<Item = u64> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    (0..TIMERS).map(move |_| {
+      state ^= state << 13;
+      state ^= state >> 7;
+      state ^= state << 17;
+      state % (4 * SLOTS as u64 * SLOT_TICKS)
+    })
+  }
+
+  #[test]
+  fn all_timers_expire_within_their_slot() {
+    let _guard = setup();
+    let mut wheel = TimingWheel::new(Duration::ZERO);
+    for (id, ticks) in due_ticks().enumerate() {
+      wheel.insert(Duration::from_micros(ticks), id as u64, id);
+    }
+    assert_eq!(wheel.len(), TIMERS as usize);
+
+    let mut fired = alloc::vec![false; TIMERS as usize];
+    let mut last = None;
+    for slot in 0..4 * SLOTS as u64 {
+      // the end of the slot
+      let now = Duration::from_micros((slot + 1) * SLOT_TICKS - 1);
+      while let Some((due, id)) = wheel.pop_due(now) {
+        assert_eq!(slot_of(due), slot);
+        // timers due at the same time expire in the order of their sequence
+        assert!(last < Some((due, id)));
+        assert!(!fired[id]);
+        fired[id] = true;
+        last = Some((due, id));
+      }
+    }
+    assert_eq!(wheel.len(), 0);
+    assert!(fired.iter().all(|&fired| fired));
+  }
+
+  #[test]
+  #[ignore = "micro-benchmark, run with --ignored --nocapture"]
+  fn benchmark_insert_against_sorted_list() {
+    let _guard = setup();
+    let dues: Vec<Duration> = due_ticks().map(Duration::from_micros).collect();
+
+    let start = std::time::Instant::now();
+    let mut wheel = TimingWheel::new(Duration::ZERO);
+    for (id, &due) in dues.iter().enumerate() {
+      wheel.insert(due, id as u64, id);
+    }
+    let wheel_time = start.elapsed();
+
+    let start = std::time::Instant::now();
+    let mut list = BTreeMap::new();
+    for (id, &due) in dues.iter().enumerate() {
+      // the sorted list is keyed by the due time and the sequence like the scheduler does
+      list.insert((due, id as u64), id);
+    }
+    let list_time = start.elapsed();
+
+    assert_eq!(wheel.len(), list.len());
+    std::println!(
+      "inserting {} timers: timing wheel {:?}, sorted list {:?}",
+      TIMERS,
+      wheel_time,
+      list_time
+    );
+  }
+}
@@ -0,0 +1,143 @@
+/***************************************************************************************************
+ * Copyright (c) 2019 by the authors
+ *
+ * Author: André Borrmann
+ * License: Apache License 2.0
+ **************************************************************************************************/
+
+//! # Software Watchdog
+//!
+//! A watchdog built on the scheduler of this crate. Once armed the expiry function is executed after the timeout,
+//! unless the watchdog is petted in time, which pushes the expiry a whole timeout further into the future. This allows
+//! to detect a stalled activity and e.g. reset the subsystem it belongs to.
+//!
+//! This is a *software* watchdog only, it is not related to the hardware watchdog of the power management peripheral
+//! of the Raspberry Pi. The expiry function is executed like any other scheduled function, so a stalled core that
+//! keeps the timer interrupt from being handled also keeps the watchdog from expiring.
+//!
+//! # Example
+//! ```no_run
+//! # use ruspiro_timer::*;
+//! # use core::sync::atomic::{AtomicBool, Ordering};
+//! static CLOCK: MockTimeSource = MockTimeSource::new(0);
+//! static EXPIRED: AtomicBool = AtomicBool::new(false);
+//!
+//! # fn doc() {
+//! set_time_source(&CLOCK);
+//! watchdog::arm_watchdog(Duration::from_millis(10), || EXPIRED.store(true, Ordering::Release));
+//! // petting the watchdog in time keeps it from expiring
+//! for _ in 0..5 {
+//!   CLOCK.advance(8_000);
+//!   poll_schedules();
+//!   assert!(watchdog::pet());
+//! }
+//! assert!(!EXPIRED.load(Ordering::Acquire));
+//!
+//! // once it is not petted any longer it expires after the timeout
+//! CLOCK.advance(10_000);
+//! poll_schedules();
+//! assert!(EXPIRED.load(Ordering::Acquire));
+//! assert!(!watchdog::pet());
+//! # }
+//! ```
+
+use crate::*;
+use ruspiro_singleton::*;
+
+/// The armed watchdog, the handle of its pending expiry function together with its timeout
+static WATCHDOG: Singleton<Option<(ScheduleHandle, Duration)>> = Singleton::new(None);
+
+/// Arm the watchdog to execute the given function once the timeout has passed without the watchdog being petted. A
+/// watchdog that is already armed is disarmed first, its expiry function is dropped without being executed.
+pub fn arm_watchdog<F>(timeout: Duration, on_expire: F)
+where
+  F: FnOnce() + 'static + Send,
+{
+  WATCHDOG.with_mut(|watchdog| {
+    if let Some((handle, _)) = watchdog.take() {
+      cancel(&handle);
+    }
+    let handle = schedule_at(now_plus(timeout), on_expire);
+    watchdog.replace((handle, timeout));
+  });
+}
+
+/// Pet the watchdog, so it expires no earlier than its timeout from now. Returns ``false`` if the watchdog is not
+/// armed or has expired already.
+pub fn pet() -> bool {
+  WATCHDOG.with_mut(|watchdog| match watchdog {
+    Some((handle, timeout)) => reschedule(handle, *timeout),
+    None => false,
+  })
+}
+
+/// Disarm the watchdog, its expiry function is dropped without being executed. Returns ``false`` if the watchdog is
+/// not armed or has expired already.
+pub fn disarm() -> bool {
+  WATCHDOG.with_mut(|watchdog| match watchdog.take() {
+    Some((handle, _)) => cancel(&handle),
+    None => false,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{setup, CLOCK};
+  use alloc::sync::Arc;
+  use core::sync::atomic::{AtomicBool, Ordering};
+
+  /// Arm the watchdog with a timeout of 10ms, the returned flag is set once it expires
+  fn armed() -> Arc<AtomicBool> {
+    let expired = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&expired);
+    arm_watchdog(Duration::from_millis(10), move || {
+      flag.store(true, Ordering::Release)
+    });
+    expired
+  }
+
+  #[test]
+  fn petting_keeps_watchdog_alive() {
+    let _guard = setup();
+    let expired = armed();
+
+    // far beyond the timeout in total, but never a whole timeout without being petted
+    for _ in 0..10 {
+      CLOCK.advance(9_000);
+      poll_schedules();
+      assert!(pet());
+    }
+    assert!(!expired.load(Ordering::Acquire));
+  }
+
+  #[test]
+  fn watchdog_expires_without_petting() {
+    let _guard = setup();
+    let expired = armed();
+    CLOCK.advance(9_000);
+    poll_schedules();
+    assert!(pet());
+
+    CLOCK.advance(9_999);
+    poll_schedules();
+    assert!(!expired.load(Ordering::Acquire));
+    CLOCK.advance(1);
+    poll_schedules();
+    assert!(expired.load(Ordering::Acquire));
+    assert!(!pet());
+    assert!(!disarm());
+  }
+
+  #[test]
+  fn disarmed_watchdog_never_expires() {
+    let _guard = setup();
+    let expired = armed();
+
+    assert!(disarm());
+    CLOCK.advance(20_000);
+    poll_schedules();
+    assert!(!expired.load(Ordering::Acquire));
+    assert!(!pet());
+  }
+}